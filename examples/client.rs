@@ -5,4 +5,5 @@ use std::net::UdpSocket;
 pub fn main() {
     let socket = UdpSocket::bind(("0.0.0.0", 0)).unwrap();
     let conditioner = Conditioner::new(ConditionerConfig::default(), socket);
+    println!("conditioning {}", conditioner.local_addr().unwrap());
 }
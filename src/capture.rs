@@ -0,0 +1,177 @@
+//! Per-packet recording of what the conditioner did, for asserting on a
+//! specific exchange after the fact.
+
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use crate::{lock, Conditioner, ConditionerState};
+
+/// Which way a captured packet was going.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Received from the socket.
+    Inbound,
+    /// Sent through the conditioner.
+    Outbound,
+}
+
+/// What happened to a captured packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fate {
+    /// Handed on, to the caller for inbound packets or to the socket for
+    /// outbound ones.
+    Delivered,
+    /// Dropped, for any reason.
+    Dropped,
+    /// Handed to the caller as an extra copy of a packet.
+    Duplicated,
+}
+
+/// One packet seen while capturing, see [`Conditioner::start_capture`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureRecord {
+    /// When its fate was decided, on the conditioner's clock.
+    pub at: Instant,
+    /// The packet's [`DeliveryAnnotation::sequence`](crate::DeliveryAnnotation::sequence),
+    /// `None` for outbound packets.
+    pub sequence: Option<u64>,
+    pub direction: Direction,
+    /// Source of inbound packets, destination of outbound ones. `None` for
+    /// sends on a connected socket.
+    pub addr: Option<SocketAddr>,
+    pub len: usize,
+    pub fate: Fate,
+    /// Time between arriving and being delivered or dropped from the queue,
+    /// `None` if it never made it into the queue.
+    pub delay: Option<Duration>,
+}
+
+impl<S, R> Conditioner<S, R> {
+    /// Starts recording every packet's fate, clearing anything recorded by a
+    /// previous capture that wasn't stopped.
+    ///
+    /// Packets filtered out by the allowlist aren't recorded. Keeps a record
+    /// per packet in memory, so it is meant for short windows in tests.
+    pub fn start_capture(&self) {
+        lock(&self.state).capture = Some(Vec::new());
+    }
+
+    /// Stops recording and returns the records in the order fates were
+    /// decided, empty if no capture was running.
+    pub fn stop_capture(&self) -> Vec<CaptureRecord> {
+        lock(&self.state).capture.take().unwrap_or_default()
+    }
+}
+
+impl CaptureRecord {
+    /// Inbound packet dropped before it made it into the queue.
+    pub(crate) fn dropped_on_arrival(
+        at: Instant,
+        sequence: u64,
+        addr: SocketAddr,
+        len: usize,
+    ) -> Self {
+        CaptureRecord {
+            at,
+            sequence: Some(sequence),
+            direction: Direction::Inbound,
+            addr: Some(addr),
+            len,
+            fate: Fate::Dropped,
+            delay: None,
+        }
+    }
+}
+
+impl ConditionerState {
+    /// Records a packet if a capture is running, only building the record
+    /// then.
+    pub(crate) fn capture(&mut self, record: impl FnOnce() -> CaptureRecord) {
+        if let Some(capture) = &mut self.capture {
+            capture.push(record());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Direction, Fate};
+    use crate::{
+        clock::{Clock, MockClock},
+        random::tests::LossRolls,
+        tests::{conditioner, peer, recv_all, MockSocket},
+        Conditioner, ConditionerConfig, SocketLike,
+    };
+
+    #[test]
+    fn records_each_fate_in_order() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(10))
+            .packet_loss_ratio(0.5)
+            .dup_ack_on_loss(1)
+            .build()
+            .unwrap();
+        let random = LossRolls {
+            loss: vec![0.9, 0.1, 0.9],
+            drawn: 0,
+        };
+        let mut conditioner = Conditioner::with_random(config, MockSocket::default(), random);
+        conditioner.set_clock(Clock::Mock(MockClock::new()));
+        let start = conditioner.clock().now();
+
+        conditioner.start_capture();
+        for i in 0..3 {
+            conditioner.inject(peer(1), vec![i; 10]);
+        }
+        conditioner.advance(Duration::from_millis(10));
+        assert_eq!(recv_all(&conditioner).len(), 3);
+        conditioner.send_to(&[0; 5], peer(2)).unwrap();
+        let records = conditioner.stop_capture();
+
+        let fates: Vec<_> = records
+            .iter()
+            .map(|record| (record.sequence, record.direction, record.fate))
+            .collect();
+        assert_eq!(
+            fates,
+            [
+                (Some(1), Direction::Inbound, Fate::Dropped),
+                (Some(0), Direction::Inbound, Fate::Delivered),
+                (Some(2), Direction::Inbound, Fate::Delivered),
+                (Some(2), Direction::Inbound, Fate::Duplicated),
+                (None, Direction::Outbound, Fate::Delivered),
+            ]
+        );
+        assert_eq!(records[0].at, start);
+        assert_eq!(records[0].delay, None);
+        assert_eq!(records[1].delay, Some(Duration::from_millis(10)));
+        assert_eq!(records[1].addr, Some(peer(1)));
+        assert_eq!(records[1].len, 10);
+        assert_eq!(records[4].addr, Some(peer(2)));
+        assert!(conditioner.stop_capture().is_empty());
+    }
+
+    #[test]
+    fn records_queue_overflow_as_dropped() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(10))
+            .max_queue_len(1)
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+
+        conditioner.start_capture();
+        conditioner.inject(peer(1), vec![0; 4]);
+        conditioner.inject(peer(1), vec![1; 4]);
+        let records = conditioner.stop_capture();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].sequence, Some(1));
+        assert_eq!(records[0].fate, Fate::Dropped);
+        assert_eq!(records[0].delay, None);
+    }
+}
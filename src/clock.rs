@@ -0,0 +1,71 @@
+//! Time source for the conditioner, swappable for a manually driven one in tests.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Where the conditioner reads the current time from.
+#[derive(Debug, Clone, Default)]
+pub enum Clock {
+    /// `Instant::now()`.
+    #[default]
+    System,
+    /// A clock that only moves when advanced, see [`MockClock`].
+    Mock(MockClock),
+}
+
+impl Clock {
+    pub fn now(&self) -> Instant {
+        match self {
+            Clock::System => Instant::now(),
+            Clock::Mock(mock) => mock.now(),
+        }
+    }
+}
+
+/// Manually driven clock, cloned handles share the same time.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockClock {
+    /// Creates a clock frozen at the current instant.
+    pub fn new() -> Self {
+        Self {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    pub fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+
+    /// Moves the clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+        *now += duration;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_time() {
+        let mock = MockClock::new();
+        let clock = Clock::Mock(mock.clone());
+        let start = clock.now();
+        mock.advance(Duration::from_millis(100));
+        assert_eq!(clock.now(), start + Duration::from_millis(100));
+        assert_eq!(mock.now(), clock.now());
+    }
+}
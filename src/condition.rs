@@ -0,0 +1,221 @@
+//! The conditioning engine on its own, for transports that move packets
+//! themselves instead of going through a [`SocketLike`].
+//!
+//! A conditioner doesn't need a socket to make decisions, so the engine can
+//! be created over `()`, e.g. `Conditioner::new(config, ())`, and driven
+//! entirely through [`Condition`].
+//!
+//! [`SocketLike`]: crate::SocketLike
+
+use std::{io, time::Instant};
+
+use crate::{random::RandomSource, Conditioner, PacketInfo, QueuedPacket, RecvFrom};
+
+/// What to do with a received packet, see [`Condition::condition_recv`].
+#[derive(Debug, Clone)]
+pub enum ConditionDecision {
+    /// Deliver each packet once its instant has passed on the conditioner's
+    /// clock and [`Condition::condition_egress`] lets it through. May hold
+    /// more than the packet passed in, e.g. a duplicate or a packet released
+    /// from reordering, or nothing when it is held back.
+    Deliver(Vec<(Instant, QueuedPacket)>),
+    /// The packet is dropped.
+    Drop,
+}
+
+/// What to do with a datagram about to be sent, see
+/// [`Condition::condition_send`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendDecision {
+    /// Send it as is.
+    Send,
+    /// Drop it silently, reporting it as sent.
+    Drop,
+}
+
+/// Drop/delay/duplicate decisions, independent of how packets are moved.
+///
+/// A [`Conditioner`] makes its own decisions through this, so calling it
+/// directly updates the same stats, reordering and loss state. The caller is
+/// responsible for holding packets until they are due, then passing each one
+/// through [`Condition::condition_egress`].
+pub trait Condition {
+    /// Decides the fate of a packet that was just received, always as a
+    /// unicast packet for a `multicast` config.
+    fn condition_recv(&self, packet: RecvFrom) -> ConditionDecision;
+
+    /// Decides whether a packet from [`ConditionDecision::Deliver`] is handed
+    /// on once it is taken out at its instant, `due`.
+    ///
+    /// This is where the loss rolled on arrival is applied with
+    /// [`StageOrder::LatencyThenLoss`](crate::StageOrder::LatencyThenLoss),
+    /// and where packets over `max_queue_age` or caught by a link outage are
+    /// dropped. Delivered packets are counted in the stats and get their
+    /// `since_previous_delivery` filled in.
+    fn condition_egress(&self, due: Instant, packet: &mut QueuedPacket) -> bool;
+
+    /// Decides whether `buf` should go out, failing if it can't be sent at
+    /// all, e.g. because it is over `max_send_size`.
+    fn condition_send(&self, buf: &[u8]) -> io::Result<SendDecision>;
+}
+
+impl<S, R> Condition for Conditioner<S, R>
+where
+    R: RandomSource,
+{
+    fn condition_recv(&self, packet: RecvFrom) -> ConditionDecision {
+        self.decide(self.clock.now(), packet, PacketInfo::default())
+    }
+
+    fn condition_egress(&self, due: Instant, packet: &mut QueuedPacket) -> bool {
+        self.release(self.clock.now(), due, packet)
+    }
+
+    fn condition_send(&self, buf: &[u8]) -> io::Result<SendDecision> {
+        self.check_send_size(buf)?;
+        if self.black_holes(buf) {
+            return Ok(SendDecision::Drop);
+        }
+        if self.drop_if_link_down()? {
+            return Ok(SendDecision::Drop);
+        }
+        Ok(SendDecision::Send)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::{
+        clock::{Clock, MockClock},
+        tests::{conditioner, peer},
+        ConditionerConfig, SocketLike, StageOrder,
+    };
+
+    /// Engine without a socket, seeded and on a mock clock.
+    fn engine(config: ConditionerConfig) -> Conditioner<(), StdRng> {
+        let mut engine = Conditioner::with_seed(config, (), 0);
+        engine.set_clock(Clock::Mock(MockClock::new()));
+        engine
+    }
+
+    fn packet(data: &[u8]) -> RecvFrom {
+        RecvFrom {
+            addr: peer(1),
+            data: data.to_vec(),
+        }
+    }
+
+    fn delivered(decision: ConditionDecision) -> Vec<(Instant, QueuedPacket)> {
+        match decision {
+            ConditionDecision::Deliver(deliver) => deliver,
+            ConditionDecision::Drop => panic!("packet was dropped"),
+        }
+    }
+
+    #[test]
+    fn delays_without_a_socket() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let engine = engine(config);
+        let now = engine.clock().now();
+
+        let mut deliver = delivered(engine.condition_recv(packet(&[1, 2, 3])));
+        assert_eq!(deliver.len(), 1);
+        let (due, queued) = &mut deliver[0];
+        assert_eq!(*due, now + Duration::from_millis(10));
+        assert_eq!(queued.packet.data, [1, 2, 3]);
+
+        engine.advance(Duration::from_millis(10));
+        assert!(engine.condition_egress(*due, queued));
+        let stats = engine.stats();
+        assert_eq!(stats.packets_received, 1);
+        assert_eq!(stats.packets_delivered, 1);
+    }
+
+    #[test]
+    fn drops_on_arrival_without_a_socket() {
+        let config = ConditionerConfig::builder()
+            .packet_loss_ratio(1.0)
+            .build()
+            .unwrap();
+        let engine = engine(config);
+
+        assert!(matches!(
+            engine.condition_recv(packet(&[1])),
+            ConditionDecision::Drop
+        ));
+        assert_eq!(engine.stats().random_loss_drops, 1);
+    }
+
+    #[test]
+    fn drops_on_egress_without_a_socket() {
+        let config = ConditionerConfig::builder()
+            .packet_loss_ratio(1.0)
+            .stage_order(StageOrder::LatencyThenLoss)
+            .build()
+            .unwrap();
+        let engine = engine(config);
+
+        let mut deliver = delivered(engine.condition_recv(packet(&[1])));
+        assert_eq!(engine.stats().random_loss_drops, 0);
+        let (due, queued) = &mut deliver[0];
+        assert!(!engine.condition_egress(*due, queued));
+        let stats = engine.stats();
+        assert_eq!(stats.random_loss_drops, 1);
+        assert_eq!(stats.packets_delivered, 0);
+    }
+
+    #[test]
+    fn send_without_a_socket() {
+        let config = ConditionerConfig::builder()
+            .max_send_size(4)
+            .build()
+            .unwrap();
+        let engine = engine(config);
+
+        assert_eq!(engine.condition_send(&[0; 4]).unwrap(), SendDecision::Send);
+        let err = engine.condition_send(&[0; 5]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        engine.set_link_down(true);
+        assert_eq!(engine.condition_send(&[0; 4]).unwrap(), SendDecision::Drop);
+    }
+
+    #[test]
+    fn condition_recv_alongside_recv_does_not_deadlock() {
+        let config = ConditionerConfig::builder()
+            .packet_loss_ratio(0.5)
+            .stage_order(StageOrder::LatencyThenLoss)
+            .build()
+            .unwrap();
+        let conditioner = Arc::new(conditioner(config));
+
+        let engine = Arc::clone(&conditioner);
+        let handle = thread::spawn(move || {
+            for _ in 0..1000 {
+                if let ConditionDecision::Deliver(deliver) = engine.condition_recv(packet(&[1])) {
+                    for (due, mut queued) in deliver {
+                        engine.condition_egress(due, &mut queued);
+                    }
+                }
+            }
+        });
+        let mut buf = [0; 16];
+        for _ in 0..1000 {
+            conditioner.inject(peer(2), vec![2]);
+            let _ = conditioner.recv_from(&mut buf);
+        }
+        handle.join().unwrap();
+        assert_eq!(conditioner.stats().packets_received, 2000);
+    }
+}
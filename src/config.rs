@@ -0,0 +1,1360 @@
+//! Conditioner configuration and its validated construction.
+
+use std::{
+    fmt,
+    ops::{Bound, Range, RangeBounds},
+    sync::{PoisonError, RwLock},
+    time::Duration,
+};
+
+use crate::{congestion::Congestion, loss_model::LossModel};
+
+/// Order the loss and latency stages are applied to received packets in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StageOrder {
+    /// Roll loss when a packet is read from the socket, so dropped packets
+    /// never take up space in the queue.
+    #[default]
+    LossThenLatency,
+    /// Queue every packet and roll loss when it comes due, like a buffer
+    /// that drops on egress. Dropped packets still count as queued.
+    LatencyThenLoss,
+}
+
+/// Reordering applied to received packets.
+///
+/// A reordered packet is held until the next packet arrives, or for
+/// [`ReorderMode::Random`] up to `reorder_max_lookahead` packets, then
+/// delayed just enough to land right behind the last of them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReorderMode {
+    /// Swaps every pair of packets, so `1, 2, 3, 4` is delivered as
+    /// `2, 1, 4, 3`.
+    SwapAdjacent,
+    /// Holds each packet back with probability `chance`, in the
+    /// `0.0 ..= 1.0` range.
+    Random { chance: f32 },
+}
+
+/// Latency that varies between several modes, e.g. a fast path and a slower
+/// fallback path, instead of a single `latency` and `jitter`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LatencyDistribution {
+    /// `(weight, latency, jitter)` modes, each packet picks one with a
+    /// chance proportional to its weight, then is delayed by that mode's
+    /// latency plus or minus up to its jitter. Jitter correlation and bias
+    /// apply across modes as usual.
+    Mixture(Vec<(f32, Duration, Duration)>),
+}
+
+impl LatencyDistribution {
+    /// Latency and jitter of the mode picked by `roll`, in the `0.0 .. 1.0`
+    /// range.
+    pub(crate) fn pick(&self, roll: f32) -> (Duration, Duration) {
+        match self {
+            LatencyDistribution::Mixture(modes) => {
+                let total = modes
+                    .iter()
+                    .fold(0.0, |total, (weight, _, _)| total + weight);
+                let mut target = roll * total;
+                for &(weight, latency, jitter) in modes {
+                    if target < weight {
+                        return (latency, jitter);
+                    }
+                    target -= weight;
+                }
+                // Only reached through rounding, or with no modes at all.
+                modes
+                    .last()
+                    .map_or((Duration::ZERO, Duration::ZERO), |&(_, latency, jitter)| {
+                        (latency, jitter)
+                    })
+            }
+        }
+    }
+}
+
+/// Which effects of a config are applied, all of them by default.
+///
+/// Turning an effect off keeps its configured values around, so effects can
+/// be flipped on and off one at a time, e.g. to bisect which one triggers a
+/// bug.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Effects {
+    /// `latency`, `min_delay`, `first_packet_extra_latency` and the latency of
+    /// `latency_distribution` modes. Delays from
+    /// [`Conditioner::set_delay_fn`](crate::Conditioner::set_delay_fn) still
+    /// apply.
+    pub latency: bool,
+    /// `jitter`, also that of `latency_distribution` modes.
+    pub jitter: bool,
+    /// `packet_loss`, `loss_model` and `congestion` loss.
+    pub loss: bool,
+    /// `duplicate_chance` and `dup_ack_on_loss`.
+    pub duplication: bool,
+    /// `reorder`.
+    pub reorder: bool,
+    /// `corruption_chance`.
+    pub corruption: bool,
+}
+
+impl Default for Effects {
+    fn default() -> Self {
+        Self {
+            latency: true,
+            jitter: true,
+            loss: true,
+            duplication: true,
+            reorder: true,
+            corruption: true,
+        }
+    }
+}
+
+/// How a [`Conditioner`](crate::Conditioner) treats the packets it receives.
+///
+/// Built with [`ConditionerConfig::builder`] or one of the presets, values
+/// are validated on the way in so a config is always in range.
+///
+/// Migrating from the public fields: struct literals become builder calls,
+/// e.g. `ConditionerConfig::builder().latency(latency).build()?`, reads
+/// become getters such as `config.latency()`, and writes become setters
+/// such as `config.set_packet_loss(0.1)?`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConditionerConfig {
+    pub(crate) latency: Duration,
+    pub(crate) jitter: Duration,
+    pub(crate) latency_distribution: Option<LatencyDistribution>,
+    pub(crate) min_delay: Duration,
+    pub(crate) first_packet_extra_latency: Duration,
+    pub(crate) sla_max_latency: Option<Duration>,
+    pub(crate) max_queue_age: Option<Duration>,
+    pub(crate) max_total_delay: Option<Duration>,
+    pub(crate) bandwidth: Option<u64>,
+    pub(crate) delivery_granularity: Option<Duration>,
+    pub(crate) jitter_correlation: f32,
+    pub(crate) jitter_bias: f32,
+    pub(crate) packet_loss: f32,
+    pub(crate) loss_model: Option<LossModel>,
+    pub(crate) congestion: Option<Congestion>,
+    pub(crate) max_queue_len: Option<usize>,
+    pub(crate) max_send_size: Option<usize>,
+    pub(crate) send_black_hole_size: Option<usize>,
+    pub(crate) stage_order: StageOrder,
+    pub(crate) gro_window: Option<Duration>,
+    pub(crate) gso_segment_size: Option<usize>,
+    pub(crate) reorder: Option<ReorderMode>,
+    pub(crate) reorder_max_lookahead: usize,
+    pub(crate) duplicate_chance: f32,
+    pub(crate) dup_ack_on_loss: u32,
+    pub(crate) corruption_chance: f32,
+    pub(crate) corruption_range: Option<Range<usize>>,
+    pub(crate) link_down_send_error: bool,
+    pub(crate) strict: bool,
+    pub(crate) max_reads_per_poll: usize,
+    pub(crate) effects: Effects,
+    pub(crate) multicast: Option<Box<ConditionerConfig>>,
+    pub(crate) dscp_priority: bool,
+    pub(crate) dscp_priority_window: Duration,
+    pub(crate) drop_consumes_bandwidth: bool,
+}
+
+impl Default for ConditionerConfig {
+    fn default() -> Self {
+        Self {
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            latency_distribution: None,
+            min_delay: Duration::ZERO,
+            first_packet_extra_latency: Duration::ZERO,
+            sla_max_latency: None,
+            max_queue_age: None,
+            max_total_delay: None,
+            bandwidth: None,
+            delivery_granularity: None,
+            jitter_correlation: 0.0,
+            jitter_bias: 0.0,
+            packet_loss: 0.0,
+            loss_model: None,
+            congestion: None,
+            max_queue_len: None,
+            max_send_size: None,
+            send_black_hole_size: None,
+            stage_order: StageOrder::default(),
+            gro_window: None,
+            gso_segment_size: None,
+            reorder: None,
+            reorder_max_lookahead: 1,
+            duplicate_chance: 0.0,
+            dup_ack_on_loss: 0,
+            corruption_chance: 0.0,
+            corruption_range: None,
+            link_down_send_error: false,
+            strict: false,
+            max_reads_per_poll: 1,
+            effects: Effects::default(),
+            multicast: None,
+            dscp_priority: false,
+            dscp_priority_window: Duration::ZERO,
+            drop_consumes_bandwidth: false,
+        }
+    }
+}
+
+/// Config returned by [`ConditionerConfig::global_default`], `None` until
+/// [`set_global_default_config`] is called.
+static GLOBAL_DEFAULT: RwLock<Option<ConditionerConfig>> = RwLock::new(None);
+
+/// Sets the process-wide config returned by
+/// [`ConditionerConfig::global_default`], replacing any previous one.
+///
+/// It can be set and read from any thread. Conditioners copy their config
+/// when they are created, so a change only affects ones created afterwards.
+/// `ConditionerConfig::default()` is unaffected.
+pub fn set_global_default_config(config: ConditionerConfig) {
+    *GLOBAL_DEFAULT
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = Some(config);
+}
+
+impl ConditionerConfig {
+    pub fn builder() -> ConditionerConfigBuilder {
+        ConditionerConfigBuilder::default()
+    }
+
+    /// The config set with [`set_global_default_config`], or the default one
+    /// if none was set, e.g. for
+    /// `Conditioner::bind(ConditionerConfig::global_default(), addr)`.
+    pub fn global_default() -> Self {
+        GLOBAL_DEFAULT
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+            .unwrap_or_default()
+    }
+
+    /// Base delay added to every packet.
+    pub fn latency(&self) -> Duration {
+        self.latency
+    }
+
+    pub fn set_latency(&mut self, latency: Duration) {
+        self.latency = latency;
+    }
+
+    /// Most a packet's delay may deviate from `latency`, either way.
+    pub fn jitter(&self) -> Duration {
+        self.jitter
+    }
+
+    pub fn set_jitter(&mut self, jitter: Duration) {
+        self.jitter = jitter;
+    }
+
+    /// Latency modes used instead of `latency` and `jitter`, if set.
+    pub fn latency_distribution(&self) -> Option<&LatencyDistribution> {
+        self.latency_distribution.as_ref()
+    }
+
+    pub fn set_latency_distribution(
+        &mut self,
+        distribution: Option<LatencyDistribution>,
+    ) -> Result<(), ConfigError> {
+        if let Some(distribution) = &distribution {
+            check_latency_distribution(distribution)?;
+        }
+        self.latency_distribution = distribution;
+        Ok(())
+    }
+
+    /// Floor for every computed delay, including [`Conditioner::set_delay_fn`]
+    /// ones. Negative jitter can't push a packet below it.
+    ///
+    /// [`Conditioner::set_delay_fn`]: crate::Conditioner::set_delay_fn
+    pub fn min_delay(&self) -> Duration {
+        self.min_delay
+    }
+
+    /// `min_delay` if latency is enabled in the effects, zero otherwise.
+    pub(crate) fn delay_floor(&self) -> Duration {
+        if self.effects.latency {
+            self.min_delay
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    pub fn set_min_delay(&mut self, min_delay: Duration) {
+        self.min_delay = min_delay;
+    }
+
+    /// Extra delay added to the first packet seen from each source, e.g. to
+    /// model connection setup cost.
+    pub fn first_packet_extra_latency(&self) -> Duration {
+        self.first_packet_extra_latency
+    }
+
+    pub fn set_first_packet_extra_latency(&mut self, extra: Duration) {
+        self.first_packet_extra_latency = extra;
+    }
+
+    /// Link capacity in bytes per second, unlimited if `None` or zero.
+    ///
+    /// Packets are sent over the link one after another, each taking
+    /// `len / bandwidth` and waiting for the ones before it, before
+    /// `latency` and jitter are added. Whether lost packets take up the link
+    /// too is up to `drop_consumes_bandwidth`.
+    pub fn bandwidth(&self) -> Option<u64> {
+        self.bandwidth
+    }
+
+    pub fn set_bandwidth(&mut self, bytes_per_second: Option<u64>) {
+        self.bandwidth = bytes_per_second;
+    }
+
+    /// Whether packets dropped on arrival still take up `bandwidth`, as if
+    /// they were lost after crossing the link, e.g. corrupted on the wire.
+    /// Off by default, where they are lost before reaching the link, e.g. at
+    /// a congested router, and leave its capacity to the packets behind them.
+    ///
+    /// With it on, loss lowers the throughput of a saturated link; with it
+    /// off, the same number of bytes per second still arrives, see
+    /// [`ConditionerConfig::saturated_goodput`]. Drops on egress with
+    /// [`StageOrder::LatencyThenLoss`] always take up the link.
+    pub fn drop_consumes_bandwidth(&self) -> bool {
+        self.drop_consumes_bandwidth
+    }
+
+    pub fn set_drop_consumes_bandwidth(&mut self, consumes: bool) {
+        self.drop_consumes_bandwidth = consumes;
+    }
+
+    /// Bytes per second that make it through with packets of `packet_size`
+    /// bytes, i.e. `bandwidth` minus what is lost, or `None` if `bandwidth`
+    /// is unlimited.
+    ///
+    /// Only looks at the loss ratios, including `congestion` loss at the
+    /// packet rate the link allows; dropped duplicates and corrupted packets
+    /// aren't accounted for.
+    pub fn estimated_goodput(&self, packet_size: usize) -> Option<f64> {
+        let bandwidth = self.bandwidth.filter(|bandwidth| *bandwidth > 0)? as f64;
+        let loss = self.loss_at_capacity(bandwidth, packet_size);
+        Some(bandwidth * (1.0 - loss as f64))
+    }
+
+    /// Like [`ConditionerConfig::estimated_goodput`], for a sender that
+    /// keeps the link saturated rather than sending at `bandwidth`.
+    ///
+    /// Lost packets only take away from the throughput if
+    /// `drop_consumes_bandwidth` is set. Otherwise they never reach the link
+    /// and the packets behind them still fill it, so this is `bandwidth`
+    /// itself.
+    pub fn saturated_goodput(&self, packet_size: usize) -> Option<f64> {
+        let bandwidth = self.bandwidth.filter(|bandwidth| *bandwidth > 0)? as f64;
+        if !self.drop_consumes_bandwidth {
+            return Some(bandwidth);
+        }
+        let loss = self.loss_at_capacity(bandwidth, packet_size);
+        Some(bandwidth * (1.0 - loss as f64))
+    }
+
+    /// Loss ratio with packets of `packet_size` bytes arriving as fast as
+    /// `bandwidth` allows.
+    fn loss_at_capacity(&self, bandwidth: f64, packet_size: usize) -> f32 {
+        let loss = match &self.congestion {
+            _ if !self.effects.loss => 0.0,
+            Some(congestion) if packet_size > 0 => {
+                let rate = bandwidth / packet_size as f64;
+                congestion.loss(self.packet_loss, rate as f32)
+            }
+            _ => match self.loss_model {
+                Some(model) => model.loss_ratio(),
+                None => self.packet_loss,
+            },
+        };
+        loss.clamp(0.0, 1.0)
+    }
+
+    /// Longest a packet may spend between arriving and being handed back,
+    /// older ones are dropped on their way out and counted in the stats'
+    /// `packets_expired`, like stale real-time traffic that is no longer
+    /// worth delivering. Includes the scheduled delay.
+    pub fn max_queue_age(&self) -> Option<Duration> {
+        self.max_queue_age
+    }
+
+    pub fn set_max_queue_age(&mut self, max_age: Option<Duration>) {
+        self.max_queue_age = max_age;
+    }
+
+    /// Cap on the delay scheduled for any single packet, applied after
+    /// everything else including `min_delay`, `bandwidth` and
+    /// `delivery_granularity`. A guardrail against misconfiguration such as a
+    /// runaway delay fn, which is cut down to the cap before it is added to
+    /// the clock, so even `Duration::MAX` is safe. Clamped packets are
+    /// counted in the stats' `delays_clamped`.
+    pub fn max_total_delay(&self) -> Option<Duration> {
+        self.max_total_delay
+    }
+
+    pub fn set_max_total_delay(&mut self, max: Option<Duration>) {
+        self.max_total_delay = max;
+    }
+
+    /// Latency budget checked on delivery: packets that spent longer than
+    /// this between arriving and being handed back are counted in the stats'
+    /// `sla_breaches`. Includes time spent waiting for the caller to recv.
+    pub fn sla_max_latency(&self) -> Option<Duration> {
+        self.sla_max_latency
+    }
+
+    pub fn set_sla_max_latency(&mut self, max: Option<Duration>) {
+        self.sla_max_latency = max;
+    }
+
+    /// Rounds every delivery instant up to the next multiple of this
+    /// duration, counted from when the conditioner's clock was set.
+    ///
+    /// Packets landing in the same bucket are released together in arrival
+    /// order, which makes delivery timing predictable on coarse clocks at the
+    /// cost of up to one bucket of extra delay and burstier delivery. A zero
+    /// duration disables the rounding.
+    pub fn delivery_granularity(&self) -> Option<Duration> {
+        self.delivery_granularity
+    }
+
+    pub fn set_delivery_granularity(&mut self, granularity: Option<Duration>) {
+        self.delivery_granularity = granularity;
+    }
+
+    /// How much each packet's jitter follows the previous packet's, in the
+    /// `0.0 .. 1.0` range. `0.0` draws every packet's jitter independently.
+    pub fn jitter_correlation(&self) -> f32 {
+        self.jitter_correlation
+    }
+
+    pub fn set_jitter_correlation(&mut self, correlation: f32) -> Result<(), ConfigError> {
+        if !(0.0..1.0).contains(&correlation) {
+            return Err(ConfigError::JitterCorrelation(correlation));
+        }
+        self.jitter_correlation = correlation;
+        Ok(())
+    }
+
+    /// Skew of the jitter, in the `-1.0 ..= 1.0` range. `0.0` is symmetric
+    /// around `latency`, `1.0` only ever adds delay and `-1.0` only ever
+    /// removes it, values in between narrow the other side proportionally.
+    pub fn jitter_bias(&self) -> f32 {
+        self.jitter_bias
+    }
+
+    pub fn set_jitter_bias(&mut self, bias: f32) -> Result<(), ConfigError> {
+        if !(-1.0..=1.0).contains(&bias) {
+            return Err(ConfigError::JitterBias(bias));
+        }
+        self.jitter_bias = bias;
+        Ok(())
+    }
+
+    /// Ratio of packets dropped, in the `0.0 ..= 1.0` range.
+    pub fn packet_loss(&self) -> f32 {
+        self.packet_loss
+    }
+
+    pub fn set_packet_loss(&mut self, ratio: f32) -> Result<(), ConfigError> {
+        if !(0.0..=1.0).contains(&ratio) {
+            return Err(ConfigError::PacketLoss(ratio));
+        }
+        self.packet_loss = ratio;
+        Ok(())
+    }
+
+    /// Bursty loss used instead of `packet_loss`, which still is the base
+    /// `congestion` loss builds on.
+    pub fn loss_model(&self) -> Option<LossModel> {
+        self.loss_model
+    }
+
+    pub fn set_loss_model(&mut self, model: Option<LossModel>) -> Result<(), ConfigError> {
+        if let Some(model) = model {
+            check_loss_model(model)?;
+        }
+        self.loss_model = model;
+        Ok(())
+    }
+
+    /// Extra loss applied when packets arrive faster than a threshold.
+    pub fn congestion(&self) -> Option<&Congestion> {
+        self.congestion.as_ref()
+    }
+
+    pub fn set_congestion(&mut self, congestion: Option<Congestion>) -> Result<(), ConfigError> {
+        if let Some(congestion) = &congestion {
+            check_congestion(congestion)?;
+        }
+        self.congestion = congestion;
+        Ok(())
+    }
+
+    /// Most packets the delivery queue holds, packets arriving while it is
+    /// full are dropped and counted in the stats' `queue_overflow_drops`,
+    /// like a drop-tail router queue. Unbounded if unset.
+    ///
+    /// A packet held back for reordering isn't in the queue yet. Only
+    /// applies to the conditioner's own queue, not to decisions made through
+    /// [`Condition`](crate::condition::Condition).
+    pub fn max_queue_len(&self) -> Option<usize> {
+        self.max_queue_len
+    }
+
+    pub fn set_max_queue_len(&mut self, max_len: Option<usize>) {
+        self.max_queue_len = max_len;
+    }
+
+    /// Largest payload `send`/`send_to` accept, larger ones are rejected
+    /// with `InvalidInput` before reaching the socket.
+    pub fn max_send_size(&self) -> Option<usize> {
+        self.max_send_size
+    }
+
+    pub fn set_max_send_size(&mut self, max_send_size: Option<usize>) {
+        self.max_send_size = max_send_size;
+    }
+
+    /// Largest datagram that makes it out. Larger sends are dropped silently
+    /// and reported as sent, like a path MTU black hole that swallows them
+    /// without an ICMP error, while received packets of any size still pass.
+    ///
+    /// With `gso_segment_size` set, the segments are what is checked. Sends
+    /// over `max_send_size` are rejected before this applies.
+    pub fn send_black_hole_size(&self) -> Option<usize> {
+        self.send_black_hole_size
+    }
+
+    pub fn set_send_black_hole_size(&mut self, size: Option<usize>) {
+        self.send_black_hole_size = size;
+    }
+
+    /// Whether loss is rolled before or after packets wait out their latency.
+    pub fn stage_order(&self) -> StageOrder {
+        self.stage_order
+    }
+
+    pub fn set_stage_order(&mut self, stage_order: StageOrder) {
+        self.stage_order = stage_order;
+    }
+
+    /// Simulates Linux UDP GRO: ready packets from the same source whose
+    /// delivery instants are within this window of the first are merged into
+    /// one buffer, reported through [`RecvMetadata::segment_size`](crate::RecvMetadata::segment_size).
+    ///
+    /// Like the kernel, only equally sized segments are merged, the last one
+    /// may be shorter, and merging stops once the caller's buffer is full.
+    pub fn gro_window(&self) -> Option<Duration> {
+        self.gro_window
+    }
+
+    pub fn set_gro_window(&mut self, window: Option<Duration>) {
+        self.gro_window = window;
+    }
+
+    /// Simulates Linux UDP GSO (`UDP_SEGMENT`): sends larger than this are
+    /// split into datagrams of this size, the last one may be shorter.
+    pub fn gso_segment_size(&self) -> Option<usize> {
+        self.gso_segment_size
+    }
+
+    pub fn set_gso_segment_size(&mut self, segment_size: Option<usize>) {
+        self.gso_segment_size = segment_size;
+    }
+
+    /// How received packets are reordered, if at all.
+    pub fn reorder(&self) -> Option<ReorderMode> {
+        self.reorder
+    }
+
+    pub fn set_reorder(&mut self, reorder: Option<ReorderMode>) -> Result<(), ConfigError> {
+        if let Some(ReorderMode::Random { chance }) = reorder {
+            if !(0.0..=1.0).contains(&chance) {
+                return Err(ConfigError::ReorderChance(chance));
+            }
+        }
+        self.reorder = reorder;
+        Ok(())
+    }
+
+    /// Most later packets a packet held back by [`ReorderMode::Random`] can
+    /// be overtaken by. Defaults to `1`.
+    ///
+    /// The depth is picked uniformly in `1 ..= lookahead`, and the held
+    /// packet is released right behind that many later packets, so with no
+    /// jitter it is never delivered more than `lookahead` places late.
+    /// Values below `1` are treated as `1`.
+    pub fn reorder_max_lookahead(&self) -> usize {
+        self.reorder_max_lookahead
+    }
+
+    pub fn set_reorder_max_lookahead(&mut self, lookahead: usize) {
+        self.reorder_max_lookahead = lookahead;
+    }
+
+    /// Whether sends fail with `NetworkDown` while the link is down, instead
+    /// of being dropped silently. See [`Conditioner::set_link_down`].
+    ///
+    /// [`Conditioner::set_link_down`]: crate::Conditioner::set_link_down
+    pub fn link_down_send_error(&self) -> bool {
+        self.link_down_send_error
+    }
+
+    pub fn set_link_down_send_error(&mut self, error: bool) {
+        self.link_down_send_error = error;
+    }
+
+    /// Whether misuse panics instead of silently degrading. Meant for tests,
+    /// not production.
+    ///
+    /// In strict mode the conditioner panics when:
+    /// - it is polled while its socket is blocking, as reported by
+    ///   [`SocketLike::is_nonblocking`](crate::SocketLike::is_nonblocking),
+    ///   or as last set through the conditioner for sockets that can't tell,
+    /// - the random source returns a value outside of `0.0 .. 1.0`.
+    pub fn strict(&self) -> bool {
+        self.strict
+    }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Which of the configured effects are applied.
+    pub fn effects(&self) -> Effects {
+        self.effects
+    }
+
+    pub fn set_effects(&mut self, effects: Effects) {
+        self.effects = effects;
+    }
+
+    /// Config for packets sent to a multicast group or the IPv4 broadcast
+    /// address `255.255.255.255`, e.g. to make them lossier than unicast.
+    ///
+    /// It decides the loss, delay, duplication, reordering and corruption of
+    /// those packets on arrival. Everything else, such as egress loss with
+    /// `LatencyThenLoss`, `max_queue_age` or `dup_ack_on_loss`, still comes
+    /// from the outer config, and its own `multicast` is ignored.
+    /// Subnet-directed broadcasts like `192.168.1.255` are treated as unicast.
+    ///
+    /// Needs the destination of each packet, which is only known for a
+    /// `UdpSocket` on Linux (read with `IP_PKTINFO`/`IPV6_PKTINFO`, enabled
+    /// on the socket on the first receive). Other sockets, a conditioner
+    /// wrapping another one and [`Condition::condition_recv`] treat every
+    /// packet as unicast.
+    ///
+    /// [`Condition::condition_recv`]: crate::condition::Condition::condition_recv
+    pub fn multicast(&self) -> Option<&ConditionerConfig> {
+        self.multicast.as_deref()
+    }
+
+    pub fn set_multicast(&mut self, multicast: Option<ConditionerConfig>) {
+        self.multicast = multicast.map(Box::new);
+    }
+
+    /// Whether packets are delivered highest DSCP first rather than in
+    /// arrival order, for QoS testing.
+    ///
+    /// Packets only compete with the others due in the same
+    /// `dscp_priority_window`. With the default zero window that is the exact
+    /// same instant, which packets arriving even a nanosecond apart won't
+    /// share unless `delivery_granularity` lines them up.
+    ///
+    /// Needs the DSCP of each packet, which is only known for a `UdpSocket`
+    /// on Linux (read with `IP_RECVTOS`/`IPV6_RECVTCLASS`, enabled on the
+    /// socket on the first receive). Packets with no known DSCP keep their
+    /// arrival order.
+    pub fn dscp_priority(&self) -> bool {
+        self.dscp_priority
+    }
+
+    pub fn set_dscp_priority(&mut self, priority: bool) {
+        self.dscp_priority = priority;
+    }
+
+    /// Length of the windows packets compete in under `dscp_priority`,
+    /// counted from when the conditioner's clock was set.
+    ///
+    /// Within a window the highest DSCP goes first, so a packet waits for a
+    /// higher priority one due later in the same window, which delays it by
+    /// up to the window. Zero by default, where only packets due at the same
+    /// instant compete.
+    pub fn dscp_priority_window(&self) -> Duration {
+        self.dscp_priority_window
+    }
+
+    pub fn set_dscp_priority_window(&mut self, window: Duration) {
+        self.dscp_priority_window = window;
+    }
+
+    /// Most datagrams read from the socket into the queue on each receive
+    /// call, before the next ready packet is delivered. Defaults to `1`.
+    ///
+    /// Raising it drains bursts out of the kernel buffer as soon as the
+    /// conditioner is polled, so they don't overflow it between calls. The
+    /// socket should be nonblocking, or every call waits for the cap to fill.
+    /// Values below `1` are treated as `1`.
+    pub fn max_reads_per_poll(&self) -> usize {
+        self.max_reads_per_poll
+    }
+
+    pub fn set_max_reads_per_poll(&mut self, reads: usize) {
+        self.max_reads_per_poll = reads;
+    }
+
+    /// Probability of a received packet being delivered twice, in the
+    /// `0.0 ..= 1.0` range.
+    pub fn duplicate_chance(&self) -> f32 {
+        self.duplicate_chance
+    }
+
+    pub fn set_duplicate_chance(&mut self, chance: f32) -> Result<(), ConfigError> {
+        if !(0.0..=1.0).contains(&chance) {
+            return Err(ConfigError::DuplicateChance(chance));
+        }
+        self.duplicate_chance = chance;
+        Ok(())
+    }
+
+    /// How many of the packets kept after a drop are delivered twice.
+    ///
+    /// A coarse stand-in for the duplicate ACKs a receiver sends after a
+    /// gap: it doesn't look at packet contents, it just duplicates whatever
+    /// comes next. A drop during the window restarts it.
+    pub fn dup_ack_on_loss(&self) -> u32 {
+        self.dup_ack_on_loss
+    }
+
+    pub fn set_dup_ack_on_loss(&mut self, packets: u32) {
+        self.dup_ack_on_loss = packets;
+    }
+
+    /// Probability of a received packet having one bit flipped, in the
+    /// `0.0 ..= 1.0` range.
+    pub fn corruption_chance(&self) -> f32 {
+        self.corruption_chance
+    }
+
+    pub fn set_corruption_chance(&mut self, chance: f32) -> Result<(), ConfigError> {
+        if !(0.0..=1.0).contains(&chance) {
+            return Err(ConfigError::CorruptionChance(chance));
+        }
+        self.corruption_chance = chance;
+        Ok(())
+    }
+
+    /// Byte offsets corruption may flip a bit in, e.g. `8..usize::MAX` to
+    /// keep an 8 byte header intact. Any byte can be hit if unset.
+    ///
+    /// The range is cut down to each packet's length, packets with no bytes
+    /// in it are never corrupted.
+    pub fn corruption_range(&self) -> Option<Range<usize>> {
+        self.corruption_range.clone()
+    }
+
+    pub fn set_corruption_range(&mut self, range: Option<Range<usize>>) {
+        self.corruption_range = range;
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if !(0.0..=1.0).contains(&self.packet_loss) {
+            return Err(ConfigError::PacketLoss(self.packet_loss));
+        }
+        if let Some(congestion) = &self.congestion {
+            check_congestion(congestion)?;
+        }
+        if let Some(model) = self.loss_model {
+            check_loss_model(model)?;
+        }
+        if let Some(distribution) = &self.latency_distribution {
+            check_latency_distribution(distribution)?;
+        }
+        if !(0.0..1.0).contains(&self.jitter_correlation) {
+            return Err(ConfigError::JitterCorrelation(self.jitter_correlation));
+        }
+        if !(-1.0..=1.0).contains(&self.jitter_bias) {
+            return Err(ConfigError::JitterBias(self.jitter_bias));
+        }
+        if let Some(ReorderMode::Random { chance }) = self.reorder {
+            if !(0.0..=1.0).contains(&chance) {
+                return Err(ConfigError::ReorderChance(chance));
+            }
+        }
+        if !(0.0..=1.0).contains(&self.duplicate_chance) {
+            return Err(ConfigError::DuplicateChance(self.duplicate_chance));
+        }
+        if !(0.0..=1.0).contains(&self.corruption_chance) {
+            return Err(ConfigError::CorruptionChance(self.corruption_chance));
+        }
+        Ok(())
+    }
+}
+
+fn check_congestion(congestion: &Congestion) -> Result<(), ConfigError> {
+    if congestion.window.is_zero() {
+        return Err(ConfigError::CongestionWindow);
+    }
+    if !(congestion.threshold.is_finite() && congestion.threshold >= 0.0) {
+        return Err(ConfigError::CongestionThreshold(congestion.threshold));
+    }
+    if !(congestion.factor.is_finite() && congestion.factor >= 0.0) {
+        return Err(ConfigError::CongestionFactor(congestion.factor));
+    }
+    Ok(())
+}
+
+fn check_latency_distribution(distribution: &LatencyDistribution) -> Result<(), ConfigError> {
+    let LatencyDistribution::Mixture(modes) = distribution;
+    for &(weight, _, _) in modes {
+        if !(weight.is_finite() && weight >= 0.0) {
+            return Err(ConfigError::LatencyWeight(weight));
+        }
+    }
+    let total = modes
+        .iter()
+        .fold(0.0, |total, (weight, _, _)| total + weight);
+    if !(total.is_finite() && total > 0.0) {
+        return Err(ConfigError::LatencyWeight(total));
+    }
+    Ok(())
+}
+
+fn check_loss_model(model: LossModel) -> Result<(), ConfigError> {
+    for chance in [model.p, model.r] {
+        if !(0.0..=1.0).contains(&chance) {
+            return Err(ConfigError::LossModelChance(chance));
+        }
+    }
+    Ok(())
+}
+
+/// Why a [`ConditionerConfigBuilder`] refused to build.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// Packet loss ratio outside of `0.0 ..= 1.0`.
+    PacketLoss(f32),
+    /// Loss model `p` or `r` outside of `0.0 ..= 1.0`.
+    LossModelChance(f32),
+    /// Latency mixture weight that is negative or not finite, or weights
+    /// that don't add up to a positive total, which is given then.
+    LatencyWeight(f32),
+    /// Jitter correlation outside of `0.0 .. 1.0`.
+    JitterCorrelation(f32),
+    /// Congestion window of zero, over which no rate can be measured.
+    CongestionWindow,
+    /// Congestion threshold that is negative or not finite.
+    CongestionThreshold(f32),
+    /// Congestion factor that is negative or not finite.
+    CongestionFactor(f32),
+    /// Jitter bias outside of `-1.0 ..= 1.0`.
+    JitterBias(f32),
+    /// Random reorder chance outside of `0.0 ..= 1.0`.
+    ReorderChance(f32),
+    /// Duplicate chance outside of `0.0 ..= 1.0`.
+    DuplicateChance(f32),
+    /// Corruption chance outside of `0.0 ..= 1.0`.
+    CorruptionChance(f32),
+    /// Link distance in km that is NaN or infinite.
+    Distance(f64),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::PacketLoss(loss) => write!(
+                f,
+                "packet loss ratio {} is outside of 0.0..=1.0 (0..=100 percent)",
+                loss
+            ),
+            ConfigError::LossModelChance(chance) => {
+                write!(f, "loss model chance {} is outside of 0.0..=1.0", chance)
+            }
+            ConfigError::LatencyWeight(weight) => write!(
+                f,
+                "latency mixture weight {} is invalid, weights must be non-negative with a positive total",
+                weight
+            ),
+            ConfigError::JitterCorrelation(correlation) => write!(
+                f,
+                "jitter correlation {} is outside of 0.0..1.0",
+                correlation
+            ),
+            ConfigError::CongestionWindow => write!(f, "congestion window is zero"),
+            ConfigError::CongestionThreshold(threshold) => write!(
+                f,
+                "congestion threshold {} is negative or not finite",
+                threshold
+            ),
+            ConfigError::CongestionFactor(factor) => {
+                write!(f, "congestion factor {} is negative or not finite", factor)
+            }
+            ConfigError::JitterBias(bias) => {
+                write!(f, "jitter bias {} is outside of -1.0..=1.0", bias)
+            }
+            ConfigError::ReorderChance(chance) => {
+                write!(f, "reorder chance {} is outside of 0.0..=1.0", chance)
+            }
+            ConfigError::DuplicateChance(chance) => {
+                write!(f, "duplicate chance {} is outside of 0.0..=1.0", chance)
+            }
+            ConfigError::CorruptionChance(chance) => {
+                write!(f, "corruption chance {} is outside of 0.0..=1.0", chance)
+            }
+            ConfigError::Distance(distance) => {
+                write!(f, "link distance {} km is not finite", distance)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Builds a [`ConditionerConfig`], checking values are in range.
+#[derive(Debug, Clone, Default)]
+pub struct ConditionerConfigBuilder {
+    config: ConditionerConfig,
+}
+
+impl ConditionerConfigBuilder {
+    /// Starts from an existing config, e.g. one of the presets.
+    pub fn from_config(config: ConditionerConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.config.latency = latency;
+        self
+    }
+
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.config.jitter = jitter;
+        self
+    }
+
+    pub fn latency_distribution(mut self, distribution: LatencyDistribution) -> Self {
+        self.config.latency_distribution = Some(distribution);
+        self
+    }
+
+    pub fn min_delay(mut self, min_delay: Duration) -> Self {
+        self.config.min_delay = min_delay;
+        self
+    }
+
+    pub fn first_packet_extra_latency(mut self, extra: Duration) -> Self {
+        self.config.first_packet_extra_latency = extra;
+        self
+    }
+
+    pub fn bandwidth(mut self, bytes_per_second: u64) -> Self {
+        self.config.bandwidth = Some(bytes_per_second);
+        self
+    }
+
+    pub fn max_queue_age(mut self, max_age: Duration) -> Self {
+        self.config.max_queue_age = Some(max_age);
+        self
+    }
+
+    pub fn max_total_delay(mut self, max: Duration) -> Self {
+        self.config.max_total_delay = Some(max);
+        self
+    }
+
+    pub fn sla_max_latency(mut self, max: Duration) -> Self {
+        self.config.sla_max_latency = Some(max);
+        self
+    }
+
+    pub fn delivery_granularity(mut self, granularity: Duration) -> Self {
+        self.config.delivery_granularity = Some(granularity);
+        self
+    }
+
+    pub fn jitter_correlation(mut self, correlation: f32) -> Self {
+        self.config.jitter_correlation = correlation;
+        self
+    }
+
+    pub fn jitter_bias(mut self, bias: f32) -> Self {
+        self.config.jitter_bias = bias;
+        self
+    }
+
+    /// Packet loss as a ratio, `0.3` drops 30% of packets.
+    pub fn packet_loss_ratio(mut self, ratio: f32) -> Self {
+        self.config.packet_loss = ratio;
+        self
+    }
+
+    /// Packet loss as a percentage, `30.0` drops 30% of packets.
+    pub fn packet_loss_percent(mut self, percent: f32) -> Self {
+        self.config.packet_loss = percent / 100.0;
+        self
+    }
+
+    pub fn loss_model(mut self, model: LossModel) -> Self {
+        self.config.loss_model = Some(model);
+        self
+    }
+
+    pub fn congestion(mut self, congestion: Congestion) -> Self {
+        self.config.congestion = Some(congestion);
+        self
+    }
+
+    pub fn max_send_size(mut self, max_send_size: usize) -> Self {
+        self.config.max_send_size = Some(max_send_size);
+        self
+    }
+
+    pub fn max_queue_len(mut self, max_len: usize) -> Self {
+        self.config.max_queue_len = Some(max_len);
+        self
+    }
+
+    pub fn send_black_hole_size(mut self, size: usize) -> Self {
+        self.config.send_black_hole_size = Some(size);
+        self
+    }
+
+    pub fn stage_order(mut self, stage_order: StageOrder) -> Self {
+        self.config.stage_order = stage_order;
+        self
+    }
+
+    pub fn gro_window(mut self, window: Duration) -> Self {
+        self.config.gro_window = Some(window);
+        self
+    }
+
+    pub fn gso_segment_size(mut self, segment_size: usize) -> Self {
+        self.config.gso_segment_size = Some(segment_size);
+        self
+    }
+
+    pub fn reorder(mut self, reorder: ReorderMode) -> Self {
+        self.config.reorder = Some(reorder);
+        self
+    }
+
+    pub fn reorder_max_lookahead(mut self, lookahead: usize) -> Self {
+        self.config.reorder_max_lookahead = lookahead;
+        self
+    }
+
+    pub fn link_down_send_error(mut self, error: bool) -> Self {
+        self.config.link_down_send_error = error;
+        self
+    }
+
+    pub fn max_reads_per_poll(mut self, reads: usize) -> Self {
+        self.config.max_reads_per_poll = reads;
+        self
+    }
+
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.config.strict = strict;
+        self
+    }
+
+    pub fn duplicate_chance(mut self, chance: f32) -> Self {
+        self.config.duplicate_chance = chance;
+        self
+    }
+
+    pub fn dup_ack_on_loss(mut self, packets: u32) -> Self {
+        self.config.dup_ack_on_loss = packets;
+        self
+    }
+
+    pub fn corruption_chance(mut self, chance: f32) -> Self {
+        self.config.corruption_chance = chance;
+        self
+    }
+
+    /// Takes any range, so `corruption_range(8..)` leaves the first 8 bytes
+    /// alone.
+    pub fn corruption_range(mut self, range: impl RangeBounds<usize>) -> Self {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.saturating_add(1),
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => usize::MAX,
+        };
+        self.config.corruption_range = Some(start..end);
+        self
+    }
+
+    pub fn enable_latency(mut self, enabled: bool) -> Self {
+        self.config.effects.latency = enabled;
+        self
+    }
+
+    pub fn enable_jitter(mut self, enabled: bool) -> Self {
+        self.config.effects.jitter = enabled;
+        self
+    }
+
+    pub fn enable_loss(mut self, enabled: bool) -> Self {
+        self.config.effects.loss = enabled;
+        self
+    }
+
+    pub fn enable_duplication(mut self, enabled: bool) -> Self {
+        self.config.effects.duplication = enabled;
+        self
+    }
+
+    pub fn enable_reorder(mut self, enabled: bool) -> Self {
+        self.config.effects.reorder = enabled;
+        self
+    }
+
+    pub fn enable_corruption(mut self, enabled: bool) -> Self {
+        self.config.effects.corruption = enabled;
+        self
+    }
+
+    pub fn multicast(mut self, multicast: ConditionerConfig) -> Self {
+        self.config.multicast = Some(Box::new(multicast));
+        self
+    }
+
+    pub fn dscp_priority(mut self, priority: bool) -> Self {
+        self.config.dscp_priority = priority;
+        self
+    }
+
+    pub fn dscp_priority_window(mut self, window: Duration) -> Self {
+        self.config.dscp_priority_window = window;
+        self
+    }
+
+    pub fn drop_consumes_bandwidth(mut self, consumes: bool) -> Self {
+        self.config.drop_consumes_bandwidth = consumes;
+        self
+    }
+
+    pub fn build(self) -> Result<ConditionerConfig, ConfigError> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn goodput_follows_drop_consumes_bandwidth_only_when_saturated() {
+        let builder = ConditionerConfig::builder()
+            .bandwidth(1000)
+            .packet_loss_ratio(0.5);
+        let config = builder.clone().build().unwrap();
+        assert_eq!(config.estimated_goodput(100), Some(500.0));
+        assert_eq!(config.saturated_goodput(100), Some(1000.0));
+
+        let config = builder.drop_consumes_bandwidth(true).build().unwrap();
+        assert_eq!(config.estimated_goodput(100), Some(500.0));
+        assert_eq!(config.saturated_goodput(100), Some(500.0));
+
+        let unlimited = ConditionerConfig::default();
+        assert_eq!(unlimited.saturated_goodput(100), None);
+    }
+
+    #[test]
+    fn packet_loss_percent_is_a_ratio() {
+        let config = ConditionerConfig::builder()
+            .packet_loss_percent(30.0)
+            .build()
+            .unwrap();
+        assert!((config.packet_loss - 0.3).abs() < 1e-6);
+
+        let config = ConditionerConfig::builder()
+            .packet_loss_ratio(0.3)
+            .build()
+            .unwrap();
+        assert_eq!(config.packet_loss, 0.3);
+    }
+
+    #[test]
+    fn rejects_packet_loss_out_of_range() {
+        let err = ConditionerConfig::builder()
+            .packet_loss_percent(130.0)
+            .build()
+            .unwrap_err();
+        assert_eq!(err, ConfigError::PacketLoss(1.3));
+        assert_eq!(
+            err.to_string(),
+            "packet loss ratio 1.3 is outside of 0.0..=1.0 (0..=100 percent)"
+        );
+        let err = ConditionerConfig::builder()
+            .packet_loss_ratio(30.0)
+            .build()
+            .unwrap_err();
+        assert_eq!(err, ConfigError::PacketLoss(30.0));
+        assert!(ConditionerConfig::builder()
+            .packet_loss_percent(100.0)
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    fn set_packet_loss_rejects_out_of_range() {
+        let mut config = ConditionerConfig::default();
+        assert_eq!(config.set_packet_loss(0.5), Ok(()));
+        assert_eq!(
+            config.set_packet_loss(1.5),
+            Err(ConfigError::PacketLoss(1.5))
+        );
+        assert_eq!(
+            config.set_packet_loss(-0.1),
+            Err(ConfigError::PacketLoss(-0.1))
+        );
+        assert!(config.set_packet_loss(f32::NAN).is_err());
+        assert_eq!(config.packet_loss(), 0.5);
+    }
+
+    #[test]
+    fn rejects_invalid_congestion() {
+        let valid = Congestion {
+            window: Duration::from_secs(1),
+            threshold: 10.0,
+            factor: 0.1,
+        };
+        let invalid = [
+            (
+                Congestion {
+                    window: Duration::ZERO,
+                    ..valid.clone()
+                },
+                ConfigError::CongestionWindow,
+            ),
+            (
+                Congestion {
+                    threshold: -1.0,
+                    ..valid.clone()
+                },
+                ConfigError::CongestionThreshold(-1.0),
+            ),
+            (
+                Congestion {
+                    factor: -0.1,
+                    ..valid.clone()
+                },
+                ConfigError::CongestionFactor(-0.1),
+            ),
+        ];
+        for (congestion, expected) in invalid {
+            let err = ConditionerConfig::builder()
+                .congestion(congestion.clone())
+                .build()
+                .unwrap_err();
+            assert_eq!(err, expected);
+            let mut config = ConditionerConfig::default();
+            assert_eq!(config.set_congestion(Some(congestion)), Err(expected));
+            assert_eq!(config.congestion(), None);
+        }
+
+        for nan in [
+            Congestion {
+                threshold: f32::NAN,
+                ..valid.clone()
+            },
+            Congestion {
+                factor: f32::NAN,
+                ..valid.clone()
+            },
+        ] {
+            assert!(ConditionerConfig::builder()
+                .congestion(nan)
+                .build()
+                .is_err());
+        }
+
+        let mut config = ConditionerConfig::default();
+        assert_eq!(config.set_congestion(Some(valid.clone())), Ok(()));
+        assert_eq!(config.congestion(), Some(&valid));
+        assert_eq!(config.set_congestion(None), Ok(()));
+        assert_eq!(config.congestion(), None);
+    }
+
+    #[test]
+    fn estimated_goodput_subtracts_loss() {
+        let config = ConditionerConfig::builder()
+            .bandwidth(10_000)
+            .packet_loss_ratio(0.25)
+            .build()
+            .unwrap();
+        assert_eq!(config.estimated_goodput(100), Some(7500.0));
+
+        // 100 packets per second at 100 bytes, 10 over the threshold.
+        let config = ConditionerConfig::builder()
+            .bandwidth(10_000)
+            .congestion(Congestion {
+                window: Duration::from_secs(1),
+                threshold: 90.0,
+                factor: 0.01,
+            })
+            .build()
+            .unwrap();
+        let goodput = config.estimated_goodput(100).unwrap();
+        assert!((goodput - 9000.0).abs() < 1.0, "{goodput}");
+    }
+
+    #[test]
+    fn estimated_goodput_is_none_when_unlimited() {
+        assert_eq!(ConditionerConfig::default().estimated_goodput(100), None);
+        let config = ConditionerConfig::builder().bandwidth(0).build().unwrap();
+        assert_eq!(config.estimated_goodput(100), None);
+    }
+
+    #[test]
+    fn mixture_picks_modes_by_weight() {
+        let mixture = LatencyDistribution::Mixture(vec![
+            (1.0, Duration::from_millis(1), Duration::ZERO),
+            (3.0, Duration::from_millis(2), Duration::ZERO),
+        ]);
+        assert_eq!(mixture.pick(0.2).0, Duration::from_millis(1));
+        assert_eq!(mixture.pick(0.3).0, Duration::from_millis(2));
+        assert_eq!(mixture.pick(1.0).0, Duration::from_millis(2));
+        let empty = LatencyDistribution::Mixture(Vec::new());
+        assert_eq!(empty.pick(0.5), (Duration::ZERO, Duration::ZERO));
+    }
+
+    #[test]
+    fn global_default_is_shared_across_threads() {
+        // The only test touching the global default, so it starts out unset.
+        assert_eq!(
+            ConditionerConfig::global_default(),
+            ConditionerConfig::default()
+        );
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(40))
+            .packet_loss_ratio(0.1)
+            .build()
+            .unwrap();
+        std::thread::spawn({
+            let config = config.clone();
+            move || set_global_default_config(config)
+        })
+        .join()
+        .unwrap();
+
+        let conditioner = crate::tests::conditioner(ConditionerConfig::global_default());
+        assert_eq!(conditioner.config, config);
+        assert_eq!(ConditionerConfig::default().latency(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,99 @@
+//! Loss that grows with the recent packet rate.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Raises packet loss once the recent packet rate goes over a threshold.
+///
+/// The effective loss is `packet_loss + factor * (rate - threshold)`, clamped
+/// to `1.0`, where `rate` is the number of packets per second seen over the
+/// last `window`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Congestion {
+    /// Sliding window the packet rate is measured over.
+    pub window: Duration,
+    /// Packets per second the link can take before loss starts to rise.
+    pub threshold: f32,
+    /// Extra loss added for each packet per second over the threshold.
+    pub factor: f32,
+}
+
+impl Congestion {
+    /// Loss for the given base loss and packet rate.
+    pub fn loss(&self, packet_loss: f32, rate: f32) -> f32 {
+        let excess = (rate - self.threshold).max(0.0);
+        (packet_loss + self.factor * excess).min(1.0)
+    }
+}
+
+/// Tracks packet arrivals over a sliding window.
+#[derive(Debug, Default)]
+pub(crate) struct RateEstimator {
+    arrivals: VecDeque<Instant>,
+}
+
+impl RateEstimator {
+    /// Records an arrival and returns the packets per second over `window`.
+    pub(crate) fn record(&mut self, now: Instant, window: Duration) -> f32 {
+        while let Some(oldest) = self.arrivals.front() {
+            if now.duration_since(*oldest) > window {
+                self.arrivals.pop_front();
+            } else {
+                break;
+            }
+        }
+        self.arrivals.push_back(now);
+
+        if window.is_zero() {
+            return 0.0;
+        }
+        self.arrivals.len() as f32 / window.as_secs_f32()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn congestion() -> Congestion {
+        Congestion {
+            window: Duration::from_secs(1),
+            threshold: 10.0,
+            factor: 0.1,
+        }
+    }
+
+    #[test]
+    fn loss_rises_over_threshold() {
+        let congestion = congestion();
+        assert_eq!(congestion.loss(0.01, 5.0), 0.01);
+        assert_eq!(congestion.loss(0.01, 10.0), 0.01);
+        assert!((congestion.loss(0.01, 12.0) - 0.21).abs() < 1e-6);
+        assert_eq!(congestion.loss(0.01, 1000.0), 1.0);
+    }
+
+    #[test]
+    fn rate_forgets_arrivals_outside_window() {
+        let mut rate = RateEstimator::default();
+        let start = Instant::now();
+        let window = Duration::from_secs(1);
+        assert_eq!(rate.record(start, window), 1.0);
+        assert_eq!(rate.record(start + Duration::from_millis(500), window), 2.0);
+        assert_eq!(
+            rate.record(start + Duration::from_millis(1000), window),
+            3.0
+        );
+        assert_eq!(
+            rate.record(start + Duration::from_millis(1600), window),
+            2.0
+        );
+    }
+
+    #[test]
+    fn zero_window_has_no_rate() {
+        let mut rate = RateEstimator::default();
+        assert_eq!(rate.record(Instant::now(), Duration::ZERO), 0.0);
+    }
+}
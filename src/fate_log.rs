@@ -0,0 +1,238 @@
+//! Recorded per-packet decisions, for reproducing a run exactly without
+//! relying on the random source producing the same values.
+
+use std::{collections::VecDeque, fmt, str::FromStr, time::Duration};
+
+use crate::{lock, Conditioner, DropReason};
+
+/// What was decided for one received packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FateRecord {
+    /// Why the packet was dropped on arrival, `None` if it was kept.
+    pub drop: Option<DropReason>,
+    /// Delay the packet was scheduled with.
+    pub delay: Duration,
+    /// The packet was delivered twice.
+    pub duplicate: bool,
+    /// How many later packets overtook it while it was held back, `None` if
+    /// it wasn't.
+    pub reorder: Option<usize>,
+    /// Byte index and bit that were flipped.
+    pub corruption: Option<(usize, u8)>,
+}
+
+/// Decisions recorded by [`Conditioner::start_recording_fates`], in arrival
+/// order.
+///
+/// Serializes to text through `Display` and parses back with `FromStr`, so a
+/// log can be attached to a bug report. Each line is one packet: `keep`,
+/// `drop random` or `drop congestion`, the delay in nanoseconds, then any of
+/// `duplicate`, `reorder DEPTH` and `corrupt BYTE BIT`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FateLog {
+    records: Vec<FateRecord>,
+}
+
+impl FateLog {
+    pub fn records(&self) -> &[FateRecord] {
+        &self.records
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub(crate) fn push(&mut self, record: FateRecord) {
+        self.records.push(record);
+    }
+}
+
+impl fmt::Display for FateLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for record in &self.records {
+            match record.drop {
+                Some(DropReason::RandomLoss) => write!(f, "drop random")?,
+                Some(DropReason::Congestion) => write!(f, "drop congestion")?,
+                None => write!(f, "keep")?,
+            }
+            write!(f, " {}", record.delay.as_nanos())?;
+            if record.duplicate {
+                write!(f, " duplicate")?;
+            }
+            if let Some(depth) = record.reorder {
+                write!(f, " reorder {}", depth)?;
+            }
+            if let Some((byte, bit)) = record.corruption {
+                write!(f, " corrupt {} {}", byte, bit)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Error returned when parsing a [`FateLog`] fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FateLogParseError(String);
+
+impl fmt::Display for FateLogParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid fate log line: {}", self.0)
+    }
+}
+
+impl std::error::Error for FateLogParseError {}
+
+impl FromStr for FateLog {
+    type Err = FateLogParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut log = FateLog::default();
+        for line in s.lines().filter(|line| !line.trim().is_empty()) {
+            let error = || FateLogParseError(line.to_string());
+            let mut tokens = line.split_whitespace();
+            let mut record = FateRecord {
+                drop: None,
+                delay: Duration::ZERO,
+                duplicate: false,
+                reorder: None,
+                corruption: None,
+            };
+            record.drop = match tokens.next() {
+                Some("keep") => None,
+                Some("drop") => match tokens.next() {
+                    Some("random") => Some(DropReason::RandomLoss),
+                    Some("congestion") => Some(DropReason::Congestion),
+                    _ => return Err(error()),
+                },
+                _ => return Err(error()),
+            };
+            let nanos = tokens.next().and_then(|nanos| nanos.parse().ok());
+            record.delay = Duration::from_nanos(nanos.ok_or_else(error)?);
+            while let Some(token) = tokens.next() {
+                match token {
+                    "duplicate" => record.duplicate = true,
+                    "reorder" => {
+                        let depth = tokens.next().and_then(|depth| depth.parse().ok());
+                        record.reorder = Some(depth.ok_or_else(error)?);
+                    }
+                    "corrupt" => {
+                        let byte = tokens.next().and_then(|byte| byte.parse().ok());
+                        let bit = tokens.next().and_then(|bit| bit.parse().ok());
+                        record.corruption = Some(byte.zip(bit).ok_or_else(error)?);
+                    }
+                    _ => return Err(error()),
+                }
+            }
+            log.push(record);
+        }
+        Ok(log)
+    }
+}
+
+impl<S, R> Conditioner<S, R> {
+    /// Starts recording the decisions made for every received packet,
+    /// discarding anything recorded by a previous unstopped recording.
+    pub fn start_recording_fates(&self) {
+        lock(&self.state).fate_recording = Some(FateLog::default());
+    }
+
+    /// Stops recording and returns the log, empty if nothing was recording.
+    pub fn stop_recording_fates(&self) -> FateLog {
+        let mut state = lock(&self.state);
+        state.fate_recording.take().unwrap_or_default()
+    }
+
+    /// Applies the decisions in `log` to the next received packets in order,
+    /// instead of rolling the random source, replacing any replay in
+    /// progress. Once the log runs out the conditioner goes back to rolling.
+    ///
+    /// The recorded delay replaces the whole delay computation, including
+    /// `bandwidth` and `delivery_granularity`. The allowlist and link outages
+    /// still apply on top, as does egress loss with
+    /// [`StageOrder::LatencyThenLoss`](crate::StageOrder::LatencyThenLoss),
+    /// which isn't recorded.
+    pub fn replay_fates(&self, log: FateLog) {
+        lock(&self.state).fate_replay = Some(VecDeque::from(log.records));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::FateLog;
+    use crate::{
+        clock::{Clock, MockClock},
+        tests::{peer, recv_all, MockSocket},
+        Conditioner, DropReason,
+    };
+
+    /// Runs 200 packets through a chaos conditioner seeded with `seed`,
+    /// replaying `log` if given, and returns what was delivered each
+    /// millisecond along with the recorded fates.
+    fn run(seed: u64, log: Option<FateLog>) -> (Vec<(u32, Vec<u8>)>, FateLog) {
+        let mut conditioner = Conditioner::chaos_with_seed(MockSocket::default(), seed);
+        conditioner.set_clock(Clock::Mock(MockClock::new()));
+        if let Some(log) = log {
+            conditioner.replay_fates(log);
+        }
+        conditioner.start_recording_fates();
+        for i in 0..200u8 {
+            conditioner.inject(peer(1), vec![i; 8]);
+        }
+        let mut delivered = Vec::new();
+        for ms in 0..500 {
+            for (data, _) in recv_all(&conditioner) {
+                delivered.push((ms, data));
+            }
+            conditioner.advance(Duration::from_millis(1));
+        }
+        (delivered, conditioner.stop_recording_fates())
+    }
+
+    #[test]
+    fn replay_reproduces_a_run() {
+        let (recorded, log) = run(1, None);
+        assert_eq!(log.len(), 200);
+        let log: FateLog = log.to_string().parse().unwrap();
+
+        let (replayed, replayed_log) = run(2, Some(log.clone()));
+        assert_eq!(replayed, recorded);
+        assert_eq!(replayed_log, log);
+        assert_ne!(run(2, None).0, recorded);
+    }
+
+    #[test]
+    fn parses_each_decision() {
+        let log: FateLog = "keep 5 duplicate reorder 2 corrupt 3 7\n\ndrop congestion 0\n"
+            .parse()
+            .unwrap();
+        let records = log.records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].delay, Duration::from_nanos(5));
+        assert!(records[0].duplicate);
+        assert_eq!(records[0].reorder, Some(2));
+        assert_eq!(records[0].corruption, Some((3, 7)));
+        assert_eq!(records[1].drop, Some(DropReason::Congestion));
+        assert_eq!(log.to_string().parse::<FateLog>().unwrap(), log);
+
+        for line in [
+            "skip 0",
+            "keep",
+            "drop 5",
+            "keep 5 corrupt 3",
+            "keep 5 lost",
+        ] {
+            let error = line.parse::<FateLog>().unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!("invalid fate log line: {}", line)
+            );
+        }
+    }
+}
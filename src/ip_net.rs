@@ -0,0 +1,142 @@
+//! Minimal IP network (CIDR) type for source filtering.
+
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+/// An IP network such as `127.0.0.0/8` or `fe80::/10`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNet {
+    /// Returns `None` if `prefix_len` is longer than the address.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Option<Self> {
+        let max = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_len > max {
+            return None;
+        }
+        Some(IpNet { addr, prefix_len })
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Whether `ip` is inside the network. IPv4-mapped IPv6 addresses, as
+    /// seen on dual-stack sockets, match IPv4 networks.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => v4_matches(net, *ip, self.prefix_len),
+            (IpAddr::V4(net), IpAddr::V6(ip)) => ip
+                .to_ipv4_mapped()
+                .is_some_and(|ip| v4_matches(net, ip, self.prefix_len)),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => v6_matches(net, *ip, self.prefix_len),
+            (IpAddr::V6(_), IpAddr::V4(_)) => false,
+        }
+    }
+}
+
+fn v4_matches(net: Ipv4Addr, ip: Ipv4Addr, prefix_len: u8) -> bool {
+    let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+    u32::from(net) & mask == u32::from(ip) & mask
+}
+
+fn v6_matches(net: Ipv6Addr, ip: Ipv6Addr, prefix_len: u8) -> bool {
+    let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+    u128::from(net) & mask == u128::from(ip) & mask
+}
+
+/// Error parsing an [`IpNet`] from a string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpNetParseError(String);
+
+impl fmt::Display for IpNetParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid IP network: {}", self.0)
+    }
+}
+
+impl std::error::Error for IpNetParseError {}
+
+impl FromStr for IpNet {
+    type Err = IpNetParseError;
+
+    /// Parses `addr/prefix_len`, a bare address is a network of one.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || IpNetParseError(s.to_string());
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => {
+                let addr: IpAddr = addr.parse().map_err(|_| error())?;
+                (addr, prefix_len.parse().map_err(|_| error())?)
+            }
+            None => {
+                let addr: IpAddr = s.parse().map_err(|_| error())?;
+                let prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+                (addr, prefix_len)
+            }
+        };
+        IpNet::new(addr, prefix_len).ok_or_else(error)
+    }
+}
+
+impl fmt::Display for IpNet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn net(s: &str) -> IpNet {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_and_displays() {
+        assert_eq!(net("127.0.0.0/8").to_string(), "127.0.0.0/8");
+        assert_eq!(net("10.1.2.3").prefix_len(), 32);
+        assert_eq!(net("fe80::/10").prefix_len(), 10);
+        assert_eq!(net("::1").prefix_len(), 128);
+        for invalid in ["127.0.0.0/33", "::/129", "localhost/8", "10.0.0.0/x"] {
+            assert_eq!(
+                invalid.parse::<IpNet>(),
+                Err(IpNetParseError(invalid.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn contains_addresses_under_prefix() {
+        assert!(net("127.0.0.0/8").contains(&ip("127.4.5.6")));
+        assert!(!net("127.0.0.0/8").contains(&ip("128.0.0.1")));
+        assert!(net("0.0.0.0/0").contains(&ip("8.8.8.8")));
+        assert!(net("fe80::/10").contains(&ip("fe80::1")));
+        assert!(!net("fe80::/10").contains(&ip("fec0::1")));
+        assert!(net("::/0").contains(&ip("::1")));
+    }
+
+    #[test]
+    fn ipv4_networks_match_mapped_addresses() {
+        assert!(net("127.0.0.0/8").contains(&ip("::ffff:127.0.0.1")));
+        assert!(!net("127.0.0.0/8").contains(&ip("::ffff:10.0.0.1")));
+        assert!(!net("::/0").contains(&ip("127.0.0.1")));
+    }
+}
@@ -0,0 +1,275 @@
+//! A receive-side jitter buffer, playing packets out in sequence order after
+//! a fixed delay the way media receivers do.
+
+use std::{
+    collections::{btree_map::Entry, BTreeMap, VecDeque},
+    fmt, io,
+    net::SocketAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    lock,
+    random::{DefaultRandom, RandomSource},
+    wrapper::{conditioner_accessors, recv_packet},
+    Conditioner, RecvFrom, SocketLike,
+};
+
+/// Wraps a conditioner, buffering conditioned packets and releasing them in
+/// sequence order, dropping the ones that arrive too late to be played.
+///
+/// The wrapper needs a sequence extractor that reads each packet's sequence
+/// number. Packets the extractor returns `None` for skip the buffer and are
+/// delivered as they arrive. Sequence numbers are compared as they are, so
+/// protocols with wrapping sequences should extend them to 64 bits first.
+///
+/// Each packet is held until `depth` after it arrived. Then it is played out,
+/// as long as no packet with a lower sequence number is still buffered. Any
+/// packet with a sequence at or below the last one played out has missed its
+/// playout deadline and is dropped, as are duplicates of a buffered packet.
+///
+/// Playout only happens when `recv_from` is called. Each call reads
+/// everything the conditioner has ready first, so the conditioner should be
+/// nonblocking.
+pub struct JitterBuffer<S, R = DefaultRandom> {
+    conditioner: Conditioner<S, R>,
+    sequence: SequenceFn,
+    depth: Duration,
+    state: Mutex<JitterBufferState>,
+}
+
+/// User supplied extractor of a packet's sequence number.
+type SequenceFn = Box<dyn Fn(&RecvFrom) -> Option<u64> + Send + Sync>;
+
+#[derive(Debug)]
+struct JitterBufferState {
+    /// Buffered packets by sequence, with when they arrived.
+    buffered: BTreeMap<u64, (Instant, RecvFrom)>,
+    /// Packets without a sequence, waiting to be delivered.
+    unsequenced: VecDeque<RecvFrom>,
+    last_played: Option<u64>,
+    late_drops: u64,
+}
+
+impl JitterBufferState {
+    fn add(&mut self, sequence: u64, arrival: Instant, packet: RecvFrom) {
+        if self.last_played.is_some_and(|last| sequence <= last) {
+            self.late_drops += 1;
+            return;
+        }
+        match self.buffered.entry(sequence) {
+            Entry::Vacant(entry) => {
+                entry.insert((arrival, packet));
+            }
+            Entry::Occupied(_) => self.late_drops += 1,
+        }
+    }
+
+    /// Takes the lowest buffered packet off the buffer if it was held for
+    /// `depth` by `now`.
+    fn play_out(&mut self, now: Instant, depth: Duration) -> Option<RecvFrom> {
+        let entry = self.buffered.first_entry()?;
+        let (arrival, _) = entry.get();
+        if now.saturating_duration_since(*arrival) < depth {
+            return None;
+        }
+        self.last_played = Some(*entry.key());
+        Some(entry.remove().1)
+    }
+}
+
+impl<S, R> JitterBuffer<S, R> {
+    /// Wraps `conditioner`, holding every packet for `depth`. `sequence`
+    /// extracts a packet's sequence number.
+    pub fn new(
+        conditioner: Conditioner<S, R>,
+        depth: Duration,
+        sequence: impl Fn(&RecvFrom) -> Option<u64> + Send + Sync + 'static,
+    ) -> Self {
+        JitterBuffer {
+            conditioner,
+            sequence: Box::new(sequence),
+            depth,
+            state: Mutex::new(JitterBufferState {
+                buffered: BTreeMap::new(),
+                unsequenced: VecDeque::new(),
+                last_played: None,
+                late_drops: 0,
+            }),
+        }
+    }
+
+    conditioner_accessors!();
+
+    /// Number of packets waiting for their playout time.
+    pub fn buffered(&self) -> usize {
+        lock(&self.state).buffered.len()
+    }
+
+    /// Number of packets dropped for missing their playout deadline or
+    /// duplicating a buffered one.
+    pub fn late_drops(&self) -> u64 {
+        lock(&self.state).late_drops
+    }
+}
+
+impl<S, R> fmt::Debug for JitterBuffer<S, R>
+where
+    S: fmt::Debug,
+    R: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JitterBuffer")
+            .field("conditioner", &self.conditioner)
+            .field("depth", &self.depth)
+            .field("state", &self.state)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, R> SocketLike for JitterBuffer<S, R>
+where
+    S: SocketLike,
+    R: RandomSource,
+{
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.conditioner.set_nonblocking(nonblocking)
+    }
+
+    fn is_nonblocking(&self) -> Option<bool> {
+        self.conditioner.is_nonblocking()
+    }
+
+    /// Delivers the next packet without a sequence, or the next one due for
+    /// playout, returning `WouldBlock` if there is neither.
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        loop {
+            match recv_packet(&self.conditioner) {
+                Ok(packet) => {
+                    let mut state = lock(&self.state);
+                    match (self.sequence)(&packet) {
+                        Some(sequence) => {
+                            state.add(sequence, self.conditioner.clock().now(), packet)
+                        }
+                        None => state.unsequenced.push_back(packet),
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        let mut state = lock(&self.state);
+        let now = self.conditioner.clock().now();
+        let packet = match state.unsequenced.pop_front() {
+            Some(packet) => packet,
+            None => state
+                .play_out(now, self.depth)
+                .ok_or_else(|| io::Error::from(io::ErrorKind::WouldBlock))?,
+        };
+        // Same as std, excess bytes of a datagram that does not fit are discarded.
+        let len = packet.data.len().min(buf.len());
+        buf[..len].copy_from_slice(&packet.data[..len]);
+        Ok((len, packet.addr))
+    }
+
+    fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        self.conditioner.send(buf)
+    }
+
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        self.conditioner.send_to(buf, addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, time::Duration};
+
+    use rand::rngs::StdRng;
+
+    use super::JitterBuffer;
+    use crate::{
+        tests::{conditioner, peer, MockSocket},
+        ConditionerConfig, SocketLike,
+    };
+
+    /// Jitter buffer with a 20ms depth, reading the sequence from the first
+    /// byte of each packet. Packets count as arriving when the buffer reads
+    /// them, so tests play out right after injecting.
+    fn buffer() -> JitterBuffer<MockSocket, StdRng> {
+        let conditioner = conditioner(ConditionerConfig::default());
+        JitterBuffer::new(conditioner, Duration::from_millis(20), |packet| {
+            packet.data.first().map(|&sequence| sequence as u64)
+        })
+    }
+
+    fn play_out(buffer: &JitterBuffer<MockSocket, StdRng>) -> Vec<Vec<u8>> {
+        let mut played = Vec::new();
+        let mut buf = [0; 16];
+        loop {
+            match buffer.recv_from(&mut buf) {
+                Ok((len, _)) => played.push(buf[..len].to_vec()),
+                Err(err) => {
+                    assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+                    return played;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn plays_out_in_sequence_order() {
+        let buffer = buffer();
+        for sequence in [2, 1, 3] {
+            buffer.conditioner().inject(peer(1), vec![sequence]);
+        }
+        buffer.conditioner().inject(peer(1), vec![]);
+        assert_eq!(play_out(&buffer), [vec![]]);
+        assert_eq!(buffer.buffered(), 3);
+
+        buffer.conditioner().advance(Duration::from_millis(20));
+        assert_eq!(play_out(&buffer), [vec![1], vec![2], vec![3]]);
+    }
+
+    #[test]
+    fn waits_for_a_lower_sequence() {
+        let buffer = buffer();
+        buffer.conditioner().inject(peer(1), vec![3]);
+        assert!(play_out(&buffer).is_empty());
+        buffer.conditioner().advance(Duration::from_millis(10));
+        buffer.conditioner().inject(peer(1), vec![1]);
+        assert!(play_out(&buffer).is_empty());
+        buffer.conditioner().advance(Duration::from_millis(10));
+        assert!(play_out(&buffer).is_empty());
+
+        buffer.conditioner().advance(Duration::from_millis(10));
+        assert_eq!(play_out(&buffer), [vec![1], vec![3]]);
+    }
+
+    #[test]
+    fn drops_late_and_duplicate_packets() {
+        let buffer = buffer();
+        buffer.conditioner().inject(peer(1), vec![2]);
+        assert!(play_out(&buffer).is_empty());
+        buffer.conditioner().advance(Duration::from_millis(20));
+        assert_eq!(play_out(&buffer), [vec![2]]);
+
+        for sequence in [1, 2, 4, 4] {
+            buffer.conditioner().inject(peer(1), vec![sequence]);
+        }
+        assert!(play_out(&buffer).is_empty());
+        buffer.conditioner().advance(Duration::from_millis(20));
+        assert_eq!(play_out(&buffer), [vec![4]]);
+        assert_eq!(buffer.late_drops(), 3);
+    }
+
+    #[test]
+    fn debug_skips_the_sequence_fn() {
+        let debug = format!("{:?}", buffer());
+        assert!(debug.starts_with("JitterBuffer { conditioner: Conditioner {"));
+        assert!(debug.contains("depth: 20ms"));
+        assert!(debug.ends_with(", .. }"));
+    }
+}
@@ -1,70 +1,460 @@
 use std::{
+    collections::{HashSet, VecDeque},
     io,
-    net::SocketAddr,
-    ops::Add,
-    sync::{Arc, Mutex},
+    net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket},
+    ops::Range,
+    sync::{Arc, Mutex, MutexGuard, PoisonError, TryLockError},
     time::{Duration, Instant},
 };
 
-use time_queue::TimeQueue;
+use capture::{CaptureRecord, Direction, Fate};
+use clock::Clock;
+use condition::{Condition, ConditionDecision, SendDecision};
+pub use config::{
+    set_global_default_config, ConditionerConfig, Effects, LatencyDistribution, ReorderMode,
+    StageOrder,
+};
+use congestion::RateEstimator;
+use fate_log::{FateLog, FateRecord};
+use ip_net::IpNet;
+use rand::{rngs::StdRng, SeedableRng};
+use random::{DefaultRandom, RandomSource};
+use stats::ConditionerStats;
+use time_queue::{ItemContainer, TimeQueue};
 
+pub mod capture;
+pub mod clock;
+pub mod condition;
+pub mod config;
+pub mod congestion;
+pub mod fate_log;
+pub mod ip_net;
+pub mod jitter_buffer;
+pub mod loss_model;
+pub mod netem;
+pub mod presets;
+#[cfg(feature = "prometheus")]
+mod prometheus;
+pub mod random;
+pub mod reassembly;
+#[cfg(unix)]
+mod sockopt;
+pub mod stats;
+#[cfg(feature = "test-util")]
+pub mod test_util;
 pub mod time_queue;
+mod wrapper;
+
+/// Delivery instant for a packet arriving at `now`, `jitter_offset` in the
+/// `-1.0 ..= 1.0` range scales the configured jitter. Never earlier than
+/// `min_delay` after `now`. Latency and jitter only apply if enabled in the
+/// config's effects.
+pub fn instant(now: Instant, config: &ConditionerConfig, jitter_offset: f32) -> Instant {
+    instant_around(now, config, config.latency, config.jitter, jitter_offset)
+}
 
-pub fn instant(config: &ConditionerConfig) -> Instant {
-    let mut instant = Instant::now().add(config.latency);
+/// Like [`instant`], around `latency` and `jitter` instead of the config's,
+/// e.g. those of a [`LatencyDistribution::Mixture`] mode.
+fn instant_around(
+    now: Instant,
+    config: &ConditionerConfig,
+    latency: Duration,
+    jitter: Duration,
+    jitter_offset: f32,
+) -> Instant {
+    let effects = config.effects;
+    let latency = if effects.latency {
+        latency
+    } else {
+        Duration::ZERO
+    };
+    let mut instant = saturating_add(now, latency);
 
-    let jitter_percent = rand::random::<f32>(); // 0.0 .. 1.0 range
-    let jitter = config.jitter.mul_f32(jitter_percent);
-    let positive_jitter = rand::random::<bool>(); // true -> positive, false -> negative
-    if positive_jitter {
+    let jitter = if effects.jitter {
+        jitter
+    } else {
+        Duration::ZERO
+    };
+    let jitter = jitter.mul_f32(jitter_offset.abs());
+    if jitter_offset >= 0.0 {
         instant = instant.checked_add(jitter).unwrap_or(instant);
     } else {
         instant = instant.checked_sub(jitter).unwrap_or(instant);
     };
-    //println!("positive: {:?}, jitter: {:?}", positive_jitter, jitter);
 
-    instant
+    instant.max(saturating_add(now, config.delay_floor()))
+}
+
+/// `instant + duration`, or the latest instant that can be represented if
+/// that would overflow, e.g. for a delay of `Duration::MAX`.
+fn saturating_add(instant: Instant, mut duration: Duration) -> Instant {
+    loop {
+        match instant.checked_add(duration) {
+            Some(later) => return later,
+            None => duration /= 2,
+        }
+    }
+}
+
+/// Draws the next jitter offset, skewed by `jitter_bias` and blended with the
+/// `previous` one according to `jitter_correlation`.
+pub fn jitter_offset<R: RandomSource>(
+    config: &ConditionerConfig,
+    previous: f32,
+    random: &mut R,
+) -> f32 {
+    let bias = config.jitter_bias;
+    let offset = random.next_range(-1.0 + bias.max(0.0), 1.0 + bias.min(0.0));
+    config.jitter_correlation * previous + (1.0 - config.jitter_correlation) * offset
+}
+
+/// Locks `mutex`, see [`recover`] for when a thread panicked while holding it.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| recover(mutex, poisoned))
+}
+
+/// Takes the guard out of a poisoned lock and clears the poison, so a panic
+/// while it was held, e.g. in a delay fn or a strict mode assertion, doesn't
+/// break the conditioner for good. The packet being handled at the time may
+/// have been half accounted for in the stats.
+fn recover<'a, T>(mutex: &Mutex<T>, poisoned: PoisonError<MutexGuard<'a, T>>) -> MutexGuard<'a, T> {
+    mutex.clear_poison();
+    poisoned.into_inner()
+}
+
+/// Rolls whether a packet survives a flat `packet_loss` ratio.
+#[deprecated(
+    note = "conditioners roll loss themselves, this ignores `loss_model`, `congestion` and the config's effects"
+)]
+pub fn keep_packet<R: RandomSource>(packet_loss: f32, random: &mut R) -> bool {
+    random.next_f32() >= packet_loss
+}
+
+/// Whether `ip` is a multicast group or the IPv4 limited broadcast address,
+/// including IPv4-mapped ones.
+fn is_group_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_multicast() || ip.is_broadcast(),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => ip.is_multicast() || ip.is_broadcast(),
+            None => ip.is_multicast(),
+        },
+    }
+}
+
+/// Byte index and bit to flip in a packet of `len` bytes, within `range` if
+/// set, picked by `byte` and `bit` in the `0.0 .. 1.0` range. `None` if there
+/// are no bytes to pick from.
+fn corruption_target(
+    len: usize,
+    range: Option<&Range<usize>>,
+    byte: f32,
+    bit: f32,
+) -> Option<(usize, u8)> {
+    let (start, end) = match range {
+        Some(range) => (range.start, range.end.min(len)),
+        None => (0, len),
+    };
+    if start >= end {
+        return None;
+    }
+    let count = end - start;
+    let index = start + ((byte * count as f32) as usize).min(count - 1);
+    let bit = ((bit * 8.0) as u8).min(7);
+    Some((index, bit))
+}
+
+/// Flips a bit of `data`, returning whether the index was in bounds.
+fn corrupt(data: &mut [u8], (index, bit): (usize, u8)) -> bool {
+    match data.get_mut(index) {
+        Some(byte) => {
+            *byte ^= 1 << bit.min(7);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Random values for one packet, see [`RandomSource`] for the order they are
+/// drawn in.
+struct PacketDraws {
+    loss: f32,
+    jitter: f32,
+    duplicate: f32,
+    reorder: f32,
+    corruption: f32,
+    corrupted_byte: f32,
+    corrupted_bit: f32,
+    /// Only drawn if there is a `latency_distribution`.
+    latency_mode: Option<f32>,
 }
 
-pub fn keep_packet(config: &ConditionerConfig) -> bool {
-    let n = rand::random::<f32>();
-    //println!("{} < {}", n, config.packet_loss);
-    !(n < config.packet_loss)
+impl PacketDraws {
+    fn draw<R: RandomSource>(
+        config: &ConditionerConfig,
+        previous_jitter: f32,
+        random: &mut R,
+    ) -> Self {
+        PacketDraws {
+            loss: random.next_f32(),
+            jitter: jitter_offset(config, previous_jitter, random),
+            duplicate: random.next_f32(),
+            reorder: random.next_f32(),
+            corruption: random.next_f32(),
+            corrupted_byte: random.next_f32(),
+            corrupted_bit: random.next_f32(),
+            latency_mode: config
+                .latency_distribution
+                .as_ref()
+                .map(|_| random.next_f32()),
+        }
+    }
 }
 
 /// Thin wrapper around a `SocketLike` to provide mock testing of packet loss/latency.
+///
+/// A conditioner is `Send + Sync` whenever the socket is `Sync` and the
+/// random source is `Send`, so it can be shared between threads behind an
+/// `Arc`. Sending, receiving and changing the link at runtime take `&self`,
+/// with the queue, random source and stats each behind their own mutex.
+/// `recv_from` only ever tries the queue lock and returns `WouldBlock` if
+/// another thread holds it. [`Conditioner::set_clock`] and
+/// [`Conditioner::with_config_scope`] take `&mut self`, so they need the
+/// conditioner before it is shared or exclusive access to it.
+///
+/// # Chaining
+///
+/// A conditioner is itself a [`SocketLike`], so a multi-hop path is built by
+/// wrapping one conditioner in another, e.g.
+/// `Conditioner::new(hop2, Conditioner::new(hop1, socket))`. Each stage only
+/// sees a packet once the stage below has released it and then draws its own
+/// delay from its own random source, so the hops' delays simply add up.
+///
+/// Jitter is uniform over `-jitter ..= jitter` around the latency, i.e. a
+/// standard deviation of `jitter / √3` per hop. Since the draws of different
+/// hops are independent, their variances add: `n` hops with equal jitter have
+/// a combined standard deviation of `√n` times a single hop, not `n` times.
+/// This holds as long as each hop's latency is at least its jitter (packets
+/// can't be released before they arrive, which clips the lower tail), the
+/// hops don't share a seed, and `jitter_correlation` is `0.0`.
 #[derive(Debug)]
-pub struct Conditioner<S> {
+pub struct Conditioner<S, R = DefaultRandom> {
     pub config: ConditionerConfig,
     socket: S,
-    queue: Arc<Mutex<TimeQueue<RecvFrom>>>,
+    queue: Arc<Mutex<TimeQueue<QueuedPacket>>>,
+    random: Mutex<R>,
+    state: Mutex<ConditionerState>,
+    clock: Clock,
+    /// Origin `delivery_granularity` buckets are counted from.
+    epoch: Instant,
+}
+
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Conditioner<UdpSocket>>();
+    assert_send_sync::<Conditioner<UdpSocket, StdRng>>();
+};
+
+/// Bookkeeping the conditioner updates as packets pass through.
+#[derive(Debug, Default)]
+struct ConditionerState {
+    rate: RateEstimator,
+    stats: ConditionerStats,
+    jitter_offset: f32,
+    /// Consecutive drops since the last kept packet.
+    drop_run: u64,
+    /// Whether `loss_model` is in its bad state.
+    loss_burst: bool,
+    delay_fn: Option<DelayFn>,
+    dry_run: bool,
+    /// Sources packets are accepted from, everything is accepted if unset.
+    source_allowlist: Option<Vec<IpNet>>,
+    /// Where delivered packets are mirrored to, see [`Conditioner::set_tap`].
+    tap: Option<SocketAddr>,
+    /// Name put in front of logged lines, see [`Conditioner::set_label`].
+    label: String,
+    /// Errors for recv to return once they come due, see
+    /// [`Conditioner::inject_error`].
+    injected_errors: TimeQueue<io::ErrorKind>,
+    /// Packet waiting for later ones to overtake it, with its delivery
+    /// instant and how many more have to arrive before it is released.
+    held: Option<(Instant, QueuedPacket, usize)>,
+    link: LinkState,
+    /// Sources a packet has been received from, for `peer_count` and
+    /// `first_packet_extra_latency`.
+    seen_peers: HashSet<SocketAddr>,
+    /// Records of the running capture, if any.
+    capture: Option<Vec<CaptureRecord>>,
+    /// Sequence number the next received packet gets.
+    next_sequence: u64,
+    /// Packets still to duplicate since the last drop, for `dup_ack_on_loss`.
+    dup_acks_left: u32,
+    /// When the link finishes sending the last packet, for `bandwidth`.
+    busy_until: Option<Instant>,
+    /// When the last delivered packet was due, for
+    /// `DeliveryAnnotation::since_previous_delivery`.
+    last_delivery_due: Option<Instant>,
+    /// Fates of the running recording, if any.
+    fate_recording: Option<FateLog>,
+    /// Fates still to apply to received packets instead of rolling them.
+    fate_replay: Option<VecDeque<FateRecord>>,
+    /// Mode last set through the conditioner, for sockets that can't report
+    /// their own, checked in strict mode.
+    nonblocking: Option<bool>,
+    /// Whether the socket was asked to report [`PacketInfo`], done the first
+    /// time a `multicast` config or `dscp_priority` is seen.
+    packet_info: bool,
+}
+
+/// Whether the link is up, see [`Conditioner::set_link_down`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum LinkState {
+    #[default]
+    Up,
+    /// Down until the given instant, or until brought back up if `None`.
+    Down(Option<Instant>),
+}
+
+impl LinkState {
+    /// Whether the link is down at `now`, bringing it back up once a timed
+    /// outage is over.
+    fn is_down(&mut self, now: Instant) -> bool {
+        match *self {
+            LinkState::Up => false,
+            LinkState::Down(Some(until)) if now >= until => {
+                *self = LinkState::Up;
+                false
+            }
+            LinkState::Down(_) => true,
+        }
+    }
+}
+
+/// Which loss source decided to drop a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    /// `packet_loss`.
+    RandomLoss,
+    /// The extra loss from `congestion`.
+    Congestion,
+}
+
+/// User supplied replacement for the latency/jitter computation.
+struct DelayFn(Box<dyn Fn(&RecvFrom) -> Duration + Send + Sync>);
+
+impl std::fmt::Debug for DelayFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("DelayFn")
+    }
 }
 
-#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct RecvFrom {
     pub addr: SocketAddr,
     pub data: Vec<u8>,
 }
 
-#[derive(Debug)]
-pub struct ConditionerConfig {
-    pub latency: Duration,
-    pub jitter: Duration,
-    pub packet_loss: f32,
+/// A packet waiting in the queue, along with what was done to it.
+#[derive(Debug, Clone)]
+pub struct QueuedPacket {
+    pub packet: RecvFrom,
+    pub annotation: DeliveryAnnotation,
+    /// Loss roll drawn on arrival, applied on egress with
+    /// [`StageOrder::LatencyThenLoss`].
+    loss_roll: Option<f32>,
 }
 
-impl Default for ConditionerConfig {
-    fn default() -> Self {
-        Self {
-            latency: Duration::ZERO,
-            jitter: Duration::ZERO,
-            packet_loss: 0.0,
+impl QueuedPacket {
+    /// Packet for adding to the queue through [`Conditioner::queue_handle`].
+    /// Its egress loss, if any, is rolled when it leaves the queue.
+    pub fn new(packet: RecvFrom, annotation: DeliveryAnnotation) -> Self {
+        QueuedPacket {
+            packet,
+            annotation,
+            loss_roll: None,
         }
     }
 }
 
+/// What the conditioner did to a delivered packet, see
+/// [`Conditioner::recv_from_annotated`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DeliveryAnnotation {
+    /// The packet is an extra copy queued by `duplicate_chance`.
+    pub is_duplicate: bool,
+    /// The packet had a bit flipped by `corruption_chance`.
+    pub is_corrupted: bool,
+    /// The packet was held back so the next one overtook it.
+    pub was_reordered: bool,
+    /// Delay scheduled between the packet arriving and being due.
+    pub delay: Duration,
+    /// Position of the packet in arrival order among those that got past the
+    /// allowlist, starting from `0`. A duplicate shares its original's.
+    pub sequence: u64,
+    /// Time between the previously delivered packet and this one becoming
+    /// due, `None` for the first delivery. Independent of how late the caller
+    /// polls, so under `bandwidth` it is at least the packet's transmit time.
+    pub since_previous_delivery: Option<Duration>,
+    /// DSCP the packet was marked with, if the socket reported it, see
+    /// `dscp_priority`.
+    pub dscp: Option<u8>,
+}
+
+/// What a socket reports about a received datagram beyond its source, see
+/// [`SocketLike::recv_from_info`].
+///
+/// Only a `UdpSocket` on Linux reports anything, read with `recvmsg`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PacketInfo {
+    /// Address the datagram was sent to, e.g. a multicast group.
+    pub destination: Option<IpAddr>,
+    /// Differentiated services code point it was marked with, the upper six
+    /// bits of the IPv4 TOS or IPv6 traffic class.
+    pub dscp: Option<u8>,
+}
+
+/// Outcome of a [`Conditioner::recv_from_status`] call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvStatus {
+    /// A packet was copied into the buffer.
+    Delivered(usize, SocketAddr),
+    /// Nothing is ready yet, the next queued packet is due at this instant.
+    WaitingUntil(Instant),
+    /// Nothing is queued.
+    Idle,
+}
+
+/// Extra information about a delivered packet, see
+/// [`Conditioner::recv_from_with_metadata`].
+///
+/// More fields may be added later, e.g. receive timestamps and ECN marks.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RecvMetadata {
+    /// Size of each coalesced segment when `gro_window` merged several
+    /// packets into the buffer, the last segment may be shorter.
+    pub segment_size: Option<usize>,
+    /// What was done to the packet, the first one's if several were
+    /// coalesced.
+    pub annotation: DeliveryAnnotation,
+    /// Full length of the datagram if it didn't fit in the buffer and the
+    /// rest was discarded, like `MSG_TRUNC` reports it. Datagrams are read
+    /// off the socket into a 16 KiB buffer first, so larger ones are already
+    /// cut down to that.
+    pub truncated_from: Option<usize>,
+}
+
 pub trait SocketLike {
     fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+    /// Whether the socket is currently nonblocking, if it can tell. Unknown
+    /// by default.
+    fn is_nonblocking(&self) -> Option<bool> {
+        None
+    }
     fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
         match self.recv_from(buf) {
             Ok((n, _)) => Ok(n),
@@ -74,12 +464,27 @@ pub trait SocketLike {
     fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
     fn send(&self, buf: &[u8]) -> io::Result<usize>;
     fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize>;
+    /// Like `recv_from`, also returning whatever the socket reports about
+    /// the datagram. Reports nothing by default.
+    fn recv_from_info(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, PacketInfo)> {
+        let (n, addr) = self.recv_from(buf)?;
+        Ok((n, addr, PacketInfo::default()))
+    }
+    /// Asks the socket to fill in `recv_from_info`'s [`PacketInfo`] from now
+    /// on. Does nothing by default.
+    fn enable_packet_info(&self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
-impl SocketLike for std::net::UdpSocket {
+impl SocketLike for UdpSocket {
     fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
         self.set_nonblocking(nonblocking)
     }
+    #[cfg(unix)]
+    fn is_nonblocking(&self) -> Option<bool> {
+        sockopt::is_nonblocking(self).ok()
+    }
     fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
         self.recv(buf)
     }
@@ -92,73 +497,3020 @@ impl SocketLike for std::net::UdpSocket {
     fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
         self.send_to(buf, addr)
     }
+    #[cfg(target_os = "linux")]
+    fn recv_from_info(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, PacketInfo)> {
+        sockopt::recv_from_info(self, buf)
+    }
+    #[cfg(target_os = "linux")]
+    fn enable_packet_info(&self) -> io::Result<()> {
+        sockopt::enable_packet_info(self)
+    }
 }
 
-impl<S> SocketLike for Conditioner<S>
+impl<S, R> SocketLike for Conditioner<S, R>
 where
     S: SocketLike,
+    R: RandomSource,
 {
     fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
-        self.socket.set_nonblocking(nonblocking)
+        self.socket.set_nonblocking(nonblocking)?;
+        lock(&self.state).nonblocking = Some(nonblocking);
+        Ok(())
     }
 
-    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
-        if let Ok(mut queue) = self.queue.try_lock() {
-            let mut temp_buf = [0; 16384];
-            if let Ok((received, addr)) = self.socket.recv_from(&mut temp_buf) {
-                let instant = instant(&self.config);
-                if keep_packet(&self.config) {
-                    queue.add_item(
-                        instant,
-                        RecvFrom {
-                            addr: addr,
-                            data: temp_buf[..received].to_vec(),
-                        },
-                    );
-                }
-            }
-
-            if queue.has_item() {
-                if let Some(item) = queue.pop_item() {
-                    for (index, byte) in item.data.iter().enumerate() {
-                        if buf.len() > index {
-                            buf[index] = *byte;
-                        } else {
-                            return Ok((buf.len(), item.addr));
-                        }
-                    }
+    fn is_nonblocking(&self) -> Option<bool> {
+        self.socket
+            .is_nonblocking()
+            .or(lock(&self.state).nonblocking)
+    }
 
-                    return Ok((item.data.len(), item.addr));
-                }
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        match self.recv_from_status(buf)? {
+            RecvStatus::Delivered(n, addr) => Ok((n, addr)),
+            RecvStatus::WaitingUntil(_) | RecvStatus::Idle => {
+                Err(std::io::Error::from(std::io::ErrorKind::WouldBlock))
             }
         }
-
-        Err(std::io::Error::from(std::io::ErrorKind::WouldBlock))
     }
 
     fn send(&self, buf: &[u8]) -> io::Result<usize> {
-        self.socket.send(buf)
+        let decision = self.condition_send(buf)?;
+        self.capture_send(buf, None, decision);
+        match decision {
+            SendDecision::Send => self.send_segmented(buf, |segment| self.socket.send(segment)),
+            SendDecision::Drop => Ok(buf.len()),
+        }
     }
 
     fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
-        self.socket.send_to(buf, addr)
+        let decision = self.condition_send(buf)?;
+        self.capture_send(buf, Some(addr), decision);
+        match decision {
+            SendDecision::Send => {
+                self.send_segmented(buf, |segment| self.socket.send_to(segment, addr))
+            }
+            SendDecision::Drop => Ok(buf.len()),
+        }
+    }
+}
+
+/// Reads one conditioned datagram per call through [`SocketLike::recv`], for
+/// code written against `Read` on a connected socket.
+///
+/// Unlike a stream, each `read` returns a single whole datagram, truncated
+/// to `buf` if it doesn't fit with the rest discarded, and `Ok(0)` is an
+/// empty datagram rather than end of file. Nothing being due yet is a
+/// `WouldBlock` error.
+impl<S, R> io::Read for Conditioner<S, R>
+where
+    S: SocketLike,
+    R: RandomSource,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.recv(buf)
     }
 }
 
-impl<S> Conditioner<S>
+/// Sends each `write` as a single datagram through [`SocketLike::send`], so
+/// a `write_all` of a buffer over `max_send_size` fails rather than being
+/// split up.
+impl<S, R> io::Write for Conditioner<S, R>
 where
     S: SocketLike,
+    R: RandomSource,
 {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.send(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S> Conditioner<S> {
+    /// Wraps an existing socket, keeping whatever options it was set up with.
+    ///
+    /// A `socket2::Socket` configured with custom buffer sizes or reuse flags
+    /// can be wrapped with `Conditioner::from_socket2` behind the `socket2`
+    /// feature, or by converting it first, `Conditioner::new(config,
+    /// UdpSocket::from(socket))`.
     pub fn new(config: ConditionerConfig, socket: S) -> Conditioner<S> {
+        Conditioner::with_random(config, socket, DefaultRandom)
+    }
+}
+
+/// Longest a [`Conditioner::recv_from_blocking`] call sleeps before checking
+/// for packets again.
+pub const BLOCKING_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Environment variable that, when set to `1`, makes [`Conditioner::bind`]
+/// ignore its config and pass traffic through untouched.
+pub const DISABLE_ENV_VAR: &str = "LINK_CONDITIONER_DISABLE";
+
+/// `config`, or the default one if `disable`, the value of
+/// [`DISABLE_ENV_VAR`], is `1`.
+fn unless_disabled(config: ConditionerConfig, disable: Option<&str>) -> ConditionerConfig {
+    if disable != Some("1") {
+        return config;
+    }
+    #[cfg(feature = "tracing")]
+    tracing::warn!("{} is set, conditioning is disabled", DISABLE_ENV_VAR);
+    ConditionerConfig::default()
+}
+
+/// Conditioner running with a temporary config, see
+/// [`Conditioner::with_config_scope`].
+///
+/// Derefs to the conditioner, the previous config is put back when the guard
+/// is dropped, discarding any changes made to `config` in the meantime.
+#[derive(Debug)]
+pub struct ConfigScopeGuard<'a, S, R = DefaultRandom> {
+    conditioner: &'a mut Conditioner<S, R>,
+    previous: Option<ConditionerConfig>,
+}
+
+impl<S, R> std::ops::Deref for ConfigScopeGuard<'_, S, R> {
+    type Target = Conditioner<S, R>;
+
+    fn deref(&self) -> &Self::Target {
+        self.conditioner
+    }
+}
+
+impl<S, R> std::ops::DerefMut for ConfigScopeGuard<'_, S, R> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.conditioner
+    }
+}
+
+impl<S, R> Drop for ConfigScopeGuard<'_, S, R> {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            self.conditioner.config = previous;
+        }
+    }
+}
+
+impl Conditioner<UdpSocket> {
+    /// Binds a `UdpSocket` to `addr` and wraps it.
+    ///
+    /// If [`DISABLE_ENV_VAR`] is set to `1` the config is replaced with the
+    /// default one, which neither delays nor drops anything, logging a
+    /// warning with the `tracing` feature. The variable is only read here, so
+    /// e.g. CI can switch conditioning off without code changes.
+    pub fn bind<A: ToSocketAddrs>(config: ConditionerConfig, addr: A) -> io::Result<Self> {
+        let disable = std::env::var(DISABLE_ENV_VAR).ok();
+        let config = unless_disabled(config, disable.as_deref());
+        Ok(Conditioner::new(config, UdpSocket::bind(addr)?))
+    }
+
+    /// Binds a `UdpSocket` to exactly `ip`, for picking an interface on
+    /// multi-homed hosts. Sends then go out with `ip` as the source.
+    ///
+    /// Binding to an IPv6 address only receives IPv6 traffic, unless it is the
+    /// unspecified address `::`, where whether IPv4-mapped traffic is also
+    /// received depends on the OS default for `IPV6_V6ONLY`.
+    pub fn bind_on_interface(config: ConditionerConfig, ip: IpAddr, port: u16) -> io::Result<Self> {
+        Self::bind(config, SocketAddr::new(ip, port))
+    }
+
+    /// Wraps a `socket2::Socket`, keeping the options it was set up with,
+    /// e.g. buffer sizes, address reuse or nonblocking mode.
+    ///
+    /// Fails with `InvalidInput` if it isn't a datagram socket.
+    #[cfg(feature = "socket2")]
+    pub fn from_socket2(config: ConditionerConfig, socket: socket2::Socket) -> io::Result<Self> {
+        if socket.r#type()? != socket2::Type::DGRAM {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "from_socket2 needs a datagram socket",
+            ));
+        }
+        Ok(Conditioner::new(config, socket.into()))
+    }
+}
+
+impl<R> Conditioner<UdpSocket, R> {
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Connects the socket to `addr`, so `send`/`recv` and the `Read`/`Write`
+    /// impls can be used.
+    pub fn connect<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
+        self.socket.connect(addr)
+    }
+}
+
+impl<S> Conditioner<S, StdRng> {
+    /// Creates a conditioner with a seeded random source, so the same seed
+    /// and traffic produce the same drops, delays and other effects.
+    pub fn with_seed(config: ConditionerConfig, socket: S, seed: u64) -> Self {
+        Conditioner::with_random(config, socket, StdRng::seed_from_u64(seed))
+    }
+
+    /// Seeded [`ConditionerConfig::chaos`], for reproducible resilience runs.
+    pub fn chaos_with_seed(socket: S, seed: u64) -> Self {
+        Conditioner::with_seed(ConditionerConfig::chaos(), socket, seed)
+    }
+}
+
+impl<S, R> Conditioner<S, R>
+where
+    R: RandomSource,
+{
+    /// Creates a conditioner that draws its loss/jitter decisions from `random`.
+    pub fn with_random(config: ConditionerConfig, socket: S, random: R) -> Conditioner<S, R> {
         let queue = Arc::new(Mutex::new(TimeQueue::new()));
         Conditioner {
             socket,
             queue,
             config,
+            random: Mutex::new(random),
+            state: Mutex::new(ConditionerState::default()),
+            clock: Clock::System,
+            epoch: Instant::now(),
         }
     }
 
+    /// Unwraps the socket, dropping anything still queued.
+    ///
+    /// The socket can be wrapped again with [`Conditioner::new`] to resume
+    /// conditioning on the same local address.
     pub fn into_socket(self) -> S {
         self.socket
     }
+
+    /// Replaces the clock used to schedule and release packets.
+    ///
+    /// Should be set before any packets are queued, instants from different
+    /// clocks don't compare meaningfully.
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.epoch = clock.now();
+        self.clock = clock;
+    }
+
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    /// Advances the mock clock, making packets due within `duration`
+    /// deliverable on the next recv.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the conditioner uses the system clock.
+    pub fn advance(&self, duration: Duration) {
+        match &self.clock {
+            Clock::Mock(mock) => mock.advance(duration),
+            Clock::System => {
+                panic!("Conditioner::advance requires a Clock::Mock, see Conditioner::set_clock")
+            }
+        }
+    }
+
+    fn check_send_size(&self, buf: &[u8]) -> io::Result<()> {
+        match self.config.max_send_size {
+            Some(max) if buf.len() > max => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "payload of {} bytes exceeds max_send_size of {} bytes",
+                    buf.len(),
+                    max
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether a send has a datagram over `send_black_hole_size`.
+    fn black_holes(&self, buf: &[u8]) -> bool {
+        let datagram = match self.config.gso_segment_size {
+            Some(size) if size > 0 => buf.len().min(size),
+            _ => buf.len(),
+        };
+        self.config
+            .send_black_hole_size
+            .is_some_and(|size| datagram > size)
+    }
+
+    /// Returns whether a send should be dropped silently because the link is
+    /// down, or fails it if `link_down_send_error` is set.
+    fn drop_if_link_down(&self) -> io::Result<bool> {
+        let mut state = lock(&self.state);
+        if !state.link.is_down(self.clock.now()) {
+            return Ok(false);
+        }
+        if self.config.link_down_send_error {
+            return Err(io::Error::new(io::ErrorKind::NetworkDown, "link is down"));
+        }
+        state.stats.link_down_drops += 1;
+        Ok(true)
+    }
+
+    fn capture_send(&self, buf: &[u8], addr: Option<SocketAddr>, decision: SendDecision) {
+        lock(&self.state).capture(|| CaptureRecord {
+            at: self.clock.now(),
+            sequence: None,
+            direction: Direction::Outbound,
+            addr,
+            len: buf.len(),
+            fate: match decision {
+                SendDecision::Send => Fate::Delivered,
+                SendDecision::Drop => Fate::Dropped,
+            },
+            delay: None,
+        });
+    }
+
+    /// Splits `buf` into `gso_segment_size` datagrams, the way Linux's
+    /// `UDP_SEGMENT` does, returning the total bytes sent.
+    fn send_segmented(
+        &self,
+        buf: &[u8],
+        mut send: impl FnMut(&[u8]) -> io::Result<usize>,
+    ) -> io::Result<usize> {
+        match self.config.gso_segment_size {
+            Some(size) if size > 0 && buf.len() > size => {
+                let mut sent = 0;
+                for segment in buf.chunks(size) {
+                    sent += send(segment)?;
+                }
+                Ok(sent)
+            }
+            _ => send(buf),
+        }
+    }
+
+    /// Clones of every queued packet in delivery order, without draining them.
+    ///
+    /// Clones all the queued data, meant for assertions rather than hot paths.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn inspect_queue(&self) -> Vec<RecvFrom> {
+        let queue = lock(&self.queue);
+        queue.iter().map(|queued| queued.packet.clone()).collect()
+    }
+
+    /// Computes each received packet's delay with `delay_fn` instead of the
+    /// configured `latency` and `jitter`, which are ignored while it is set.
+    /// Loss still applies as usual.
+    ///
+    /// `delay_fn` runs with the conditioner's locks held, so it must not call
+    /// back into the conditioner.
+    pub fn set_delay_fn(&self, delay_fn: impl Fn(&RecvFrom) -> Duration + Send + Sync + 'static) {
+        lock(&self.state).delay_fn = Some(DelayFn(Box::new(delay_fn)));
+    }
+
+    /// Goes back to computing delays from `latency` and `jitter`.
+    pub fn clear_delay_fn(&self) {
+        lock(&self.state).delay_fn = None;
+    }
+
+    /// Shared handle to the delivery queue, for driving delivery from a
+    /// custom scheduler.
+    ///
+    /// An escape hatch, the conditioner doesn't know about changes made
+    /// through it, so:
+    /// - instants must come from this conditioner's [`clock`](Self::clock),
+    /// - packets added or removed directly aren't reflected in the stats,
+    ///   apart from being counted as delivered when a recv hands them back,
+    /// - the lock must not be held across a recv, which only tries the lock
+    ///   and returns `WouldBlock` while it is taken.
+    pub fn queue_handle(&self) -> Arc<Mutex<TimeQueue<QueuedPacket>>> {
+        Arc::clone(&self.queue)
+    }
+
+    /// Swaps in `config` until the returned guard is dropped, e.g. to run a
+    /// block of a test under harsher conditions.
+    ///
+    /// Packets already queued keep the delivery times they were given.
+    pub fn with_config_scope(&mut self, config: ConditionerConfig) -> ConfigScopeGuard<'_, S, R> {
+        let previous = std::mem::replace(&mut self.config, config);
+        ConfigScopeGuard {
+            conditioner: self,
+            previous: Some(previous),
+        }
+    }
+
+    /// Moves every queued packet into `other`, keeping how long each one
+    /// still has to wait, e.g. to hand traffic over to a new conditioner.
+    ///
+    /// A packet held back for reordering is moved too, due right away. The
+    /// packets aren't conditioned again on the way in, and this conditioner's
+    /// queue is unlocked before `other`'s is locked.
+    pub fn drain_into<S2, R2>(&self, other: &Conditioner<S2, R2>)
+    where
+        R2: RandomSource,
+    {
+        let now = self.clock.now();
+        let mut moved = Vec::new();
+        {
+            let mut queue = lock(&self.queue);
+            let mut state = lock(&self.state);
+            while let Some(entry) = queue.pop_next_entry() {
+                moved.push((entry.instant.saturating_duration_since(now), entry.item));
+            }
+            if let Some((_, held, _)) = state.held.take() {
+                moved.push((Duration::ZERO, held));
+            }
+            state.stats.bytes_currently_queued = 0;
+        }
+
+        let other_now = other.clock.now();
+        let mut queue = lock(&other.queue);
+        let mut state = lock(&other.state);
+        for (remaining, queued) in moved {
+            other.enqueue(
+                &mut queue,
+                &mut state,
+                saturating_add(other_now, remaining),
+                queued,
+            );
+        }
+    }
+
+    /// Only accepts packets whose source is inside one of `nets`, silently
+    /// discarding the rest the way a firewall would, before any conditioning.
+    pub fn set_source_allowlist(&self, nets: Vec<IpNet>) {
+        lock(&self.state).source_allowlist = Some(nets);
+    }
+
+    /// Accepts packets from every source again.
+    pub fn clear_source_allowlist(&self) {
+        lock(&self.state).source_allowlist = None;
+    }
+
+    /// Number of distinct source addresses packets were received from,
+    /// counting those past the allowlist even if they were dropped.
+    ///
+    /// Grows with every new peer until [`Conditioner::clear_peers`] is called.
+    pub fn peer_count(&self) -> usize {
+        lock(&self.state).seen_peers.len()
+    }
+
+    /// Forgets the peers seen so far, so the next packet from each of them
+    /// counts as its first again, including for `first_packet_extra_latency`.
+    pub fn clear_peers(&self) {
+        lock(&self.state).seen_peers.clear();
+    }
+
+    /// Mirrors every delivered packet to `addr` through the conditioner's
+    /// socket, for watching the conditioned stream from another tool.
+    ///
+    /// Mirroring is best-effort: failed sends are ignored. It doesn't make
+    /// the socket block, but a blocking socket with a full send buffer can
+    /// still hold up delivery until there is room.
+    pub fn set_tap(&self, addr: SocketAddr) {
+        lock(&self.state).tap = Some(addr);
+    }
+
+    /// Stops mirroring delivered packets.
+    pub fn clear_tap(&self) {
+        lock(&self.state).tap = None;
+    }
+
+    /// Names the conditioner in what it logs, so output from several
+    /// conditioners can be told apart. Empty by default, which logs lines
+    /// without a prefix.
+    pub fn set_label(&self, label: impl Into<String>) {
+        lock(&self.state).label = label.into();
+    }
+
+    pub fn label(&self) -> String {
+        lock(&self.state).label.clone()
+    }
+
+    /// Takes the link down, or brings it back up.
+    ///
+    /// While down every packet is dropped: arriving ones, ones already queued
+    /// as they come due, and sent ones (which fail instead if
+    /// [`ConditionerConfig::link_down_send_error`] is set). Unlike 100% loss
+    /// this also swallows packets that were queued before the outage.
+    pub fn set_link_down(&self, down: bool) {
+        lock(&self.state).link = if down {
+            LinkState::Down(None)
+        } else {
+            LinkState::Up
+        };
+    }
+
+    /// Takes the link down for `duration` on the conditioner's clock, after
+    /// which it comes back up by itself.
+    pub fn link_down_for(&self, duration: Duration) {
+        lock(&self.state).link = LinkState::Down(Some(saturating_add(self.clock.now(), duration)));
+    }
+
+    /// Toggles dry-run mode, where every decision is still made and counted
+    /// in the stats' `would_drop` and `would_delay`, but packets are
+    /// delivered immediately and never dropped.
+    ///
+    /// Handy for checking what a config would do to live traffic before
+    /// letting it loose.
+    pub fn set_dry_run(&self, dry_run: bool) {
+        lock(&self.state).dry_run = dry_run;
+    }
+
+    /// Snapshot of the counters so far.
+    pub fn stats(&self) -> ConditionerStats {
+        let queue_len = lock(&self.queue).len();
+        let mut stats = lock(&self.state).stats.clone();
+        stats.queue_len = queue_len;
+        stats
+    }
+
+    /// Packets waiting in the queue and how long until the next one is due,
+    /// zero if one is already ready, from a single lock of the queue.
+    pub fn pending(&self) -> (usize, Option<Duration>) {
+        let now = self.clock.now();
+        let queue = lock(&self.queue);
+        let next = queue
+            .peek_entry()
+            .map(|entry| entry.instant.saturating_duration_since(now));
+        (queue.len(), next)
+    }
+
+    /// Source of the next packet that is ready for delivery, without
+    /// removing it or copying its payload.
+    ///
+    /// Only looks at packets already queued; it doesn't read the socket. With
+    /// [`StageOrder::LatencyThenLoss`] the peeked packet may still be dropped
+    /// on its way out.
+    pub fn peek_ready_addr(&self) -> Option<SocketAddr> {
+        let now = self.clock.now();
+        let queue = lock(&self.queue);
+        queue
+            .peek_entry()
+            .filter(|entry| entry.instant <= now)
+            .map(|entry| entry.item.packet.addr)
+    }
+
+    /// [`ConditionerStats::summary`] with each line prefixed with the label,
+    /// if one is set, e.g. for printing at the end of a test.
+    pub fn summary(&self) -> String {
+        let label = self.label();
+        let summary = self.stats().summary();
+        if label.is_empty() {
+            return summary;
+        }
+        summary
+            .lines()
+            .map(|line| format!("[{}] {}\n", label, line))
+            .collect()
+    }
+
+    /// Emits [`ConditionerStats::summary`] as `info` level `tracing` events,
+    /// one per line, with the label as a `label` field if one is set.
+    ///
+    /// Meant to be called at the end of a test; nothing is logged on drop.
+    #[cfg(feature = "tracing")]
+    pub fn log_summary(&self) {
+        let label = self.label();
+        for line in self.stats().summary().lines() {
+            if label.is_empty() {
+                tracing::info!("{}", line);
+            } else {
+                tracing::info!(label = %label, "{}", line);
+            }
+        }
+    }
+
+    /// Rolls whether a packet arriving at `now` survives, attributing drops in the stats.
+    fn should_keep(&self, now: Instant, state: &mut ConditionerState, n: f32) -> bool {
+        let drop = self.roll_drop(&self.config, now, state, n);
+        self.account_drop(state, drop)
+    }
+
+    /// Attributes a drop decision in the stats, returning whether the packet
+    /// is kept, which it always is in dry-run mode.
+    fn account_drop(&self, state: &mut ConditionerState, drop: Option<DropReason>) -> bool {
+        if state.dry_run {
+            if drop.is_some() {
+                state.stats.would_drop += 1;
+            }
+            return true;
+        }
+
+        match drop {
+            Some(DropReason::RandomLoss) => state.stats.random_loss_drops += 1,
+            Some(DropReason::Congestion) => state.stats.congestion_drops += 1,
+            None => {}
+        }
+        if drop.is_some() {
+            state.drop_run += 1;
+            state.dup_acks_left = self.config.dup_ack_on_loss;
+        } else if state.drop_run > 0 {
+            *state.stats.drop_bursts.entry(state.drop_run).or_default() += 1;
+            state.drop_run = 0;
+        }
+        drop.is_none()
+    }
+
+    /// Decides whether a packet is dropped given its loss roll `n` under `config`.
+    fn roll_drop(
+        &self,
+        config: &ConditionerConfig,
+        now: Instant,
+        state: &mut ConditionerState,
+        n: f32,
+    ) -> Option<DropReason> {
+        let rate = config
+            .congestion
+            .as_ref()
+            .map(|congestion| state.rate.record(now, congestion.window));
+
+        if config.strict {
+            assert!(
+                (0.0..1.0).contains(&n),
+                "strict mode: random source returned {}, outside of 0.0..1.0",
+                n
+            );
+        }
+        if !config.effects.loss {
+            return None;
+        }
+        let lost = match &config.loss_model {
+            Some(model) => model.step(&mut state.loss_burst, n),
+            None => n < config.packet_loss,
+        };
+        if lost {
+            return Some(DropReason::RandomLoss);
+        }
+        if let (Some(congestion), Some(rate)) = (&config.congestion, rate) {
+            if n < congestion.loss(config.packet_loss, rate) {
+                return Some(DropReason::Congestion);
+            }
+        }
+        None
+    }
+
+    /// Runs a packet received at `now` through the conditioning, see
+    /// [`Condition::condition_recv`]. `info` is what the socket reported
+    /// about it, for picking the `multicast` config and `dscp_priority`.
+    fn decide(&self, now: Instant, mut packet: RecvFrom, info: PacketInfo) -> ConditionDecision {
+        let mut deliver = Vec::new();
+        let mut random = lock(&self.random);
+        let mut state = lock(&self.state);
+        if let Some(allowlist) = &state.source_allowlist {
+            let source = packet.addr.ip();
+            if !allowlist.iter().any(|net| net.contains(&source)) {
+                state.stats.packets_filtered += 1;
+                return ConditionDecision::Drop;
+            }
+        }
+        state.stats.packets_received += 1;
+        *state.stats.peer_packets.entry(packet.addr).or_default() += 1;
+        let first_packet = state.seen_peers.insert(packet.addr);
+        let sequence = state.next_sequence;
+        state.next_sequence += 1;
+        if state.link.is_down(now) {
+            state.stats.link_down_drops += 1;
+            state.capture(|| {
+                CaptureRecord::dropped_on_arrival(now, sequence, packet.addr, packet.data.len())
+            });
+            return ConditionDecision::Drop;
+        }
+        let replayed = state.fate_replay.as_mut().and_then(VecDeque::pop_front);
+        if state.fate_replay.as_ref().is_some_and(VecDeque::is_empty) {
+            state.fate_replay = None;
+        }
+        let (fate, loss_roll) = match replayed {
+            Some(fate) => (fate, None),
+            None => {
+                let config = match &self.config.multicast {
+                    Some(multicast) if info.destination.is_some_and(is_group_address) => multicast,
+                    _ => &self.config,
+                };
+                let (fate, loss_roll) =
+                    self.roll_fate(config, now, &mut state, &mut *random, &packet, first_packet);
+                (fate, Some(loss_roll))
+            }
+        };
+        drop(random);
+        if let Some(recording) = &mut state.fate_recording {
+            recording.push(fate.clone());
+        }
+
+        let instant = saturating_add(now, fate.delay);
+        let instant = if state.dry_run {
+            state.stats.would_delay = state.stats.would_delay.saturating_add(fate.delay);
+            now
+        } else {
+            instant
+        };
+        // With `LatencyThenLoss` the loss of kept packets is accounted for
+        // on egress instead.
+        let accounted =
+            fate.drop.is_some() || self.config.stage_order == StageOrder::LossThenLatency;
+        if accounted && !self.account_drop(&mut state, fate.drop) {
+            state.capture(|| {
+                CaptureRecord::dropped_on_arrival(now, sequence, packet.addr, packet.data.len())
+            });
+            return ConditionDecision::Drop;
+        }
+        let delay = instant.saturating_duration_since(now);
+        state.stats.record_delay(delay);
+        let mut annotation = DeliveryAnnotation {
+            delay,
+            sequence,
+            dscp: info.dscp,
+            ..DeliveryAnnotation::default()
+        };
+        // Rolled on arrival so toggling the stage order doesn't shift the
+        // random stream, a duplicate shares its original's.
+        let loss_roll =
+            loss_roll.filter(|_| self.config.stage_order == StageOrder::LatencyThenLoss);
+        let queued = |packet, annotation| QueuedPacket {
+            packet,
+            annotation,
+            loss_roll,
+        };
+        if state.dry_run {
+            deliver.push((instant, queued(packet, annotation)));
+            return ConditionDecision::Deliver(deliver);
+        }
+
+        if let Some(target) = fate.corruption {
+            if corrupt(&mut packet.data, target) {
+                state.stats.packets_corrupted += 1;
+                annotation.is_corrupted = true;
+            }
+        }
+        let duplicate = fate.duplicate.then(|| {
+            let annotation = DeliveryAnnotation {
+                is_duplicate: true,
+                ..annotation
+            };
+            queued(packet.clone(), annotation)
+        });
+
+        match state.held.take() {
+            Some((held_instant, held, remaining)) if remaining > 1 => {
+                deliver.push((instant, queued(packet, annotation)));
+                state.held = Some((held_instant, held, remaining - 1));
+            }
+            // Released right behind the last packet that overtook it.
+            Some((held_instant, mut held, _)) => {
+                deliver.push((instant, queued(packet, annotation)));
+                let released = held_instant.max(instant);
+                held.annotation.delay = held
+                    .annotation
+                    .delay
+                    .saturating_add(released - held_instant);
+                deliver.push((released, held));
+            }
+            None => match fate.reorder {
+                Some(depth) => {
+                    state.stats.packets_reordered += 1;
+                    annotation.was_reordered = true;
+                    let held = queued(packet, annotation);
+                    state.held = Some((instant, held, depth));
+                }
+                None => deliver.push((instant, queued(packet, annotation))),
+            },
+        }
+        if let Some(duplicate) = duplicate {
+            state.stats.packets_duplicated += 1;
+            deliver.push((instant, duplicate));
+        }
+        ConditionDecision::Deliver(deliver)
+    }
+
+    /// Decides a packet's fate from the random source, see
+    /// [`Conditioner::replay_fates`] for replaying a recorded one instead.
+    /// Also returns the loss roll, which is applied on egress with
+    /// [`StageOrder::LatencyThenLoss`].
+    fn roll_fate(
+        &self,
+        config: &ConditionerConfig,
+        now: Instant,
+        state: &mut ConditionerState,
+        random: &mut R,
+        packet: &RecvFrom,
+        first_packet: bool,
+    ) -> (FateRecord, f32) {
+        let draws = PacketDraws::draw(config, state.jitter_offset, random);
+        let max_total_delay = config.max_total_delay.unwrap_or(Duration::MAX);
+        // Set if the delay fn alone already went over `max_total_delay`.
+        let mut clamped = false;
+        let instant = match &state.delay_fn {
+            Some(delay_fn) => {
+                let delay = (delay_fn.0)(packet).max(config.delay_floor());
+                clamped = delay > max_total_delay;
+                saturating_add(now, delay.min(max_total_delay))
+            }
+            None => {
+                state.jitter_offset = draws.jitter;
+                match (&config.latency_distribution, draws.latency_mode) {
+                    (Some(distribution), Some(roll)) => {
+                        let (latency, jitter) = distribution.pick(roll);
+                        instant_around(now, config, latency, jitter, draws.jitter)
+                    }
+                    _ => instant(now, config, draws.jitter),
+                }
+            }
+        };
+        let instant = if first_packet && config.effects.latency {
+            saturating_add(instant, config.first_packet_extra_latency)
+        } else {
+            instant
+        };
+        let drop = match config.stage_order {
+            StageOrder::LossThenLatency => self.roll_drop(config, now, state, draws.loss),
+            StageOrder::LatencyThenLoss => None,
+        };
+        let instant = match config.bandwidth {
+            Some(bandwidth)
+                if bandwidth > 0 && (drop.is_none() || config.drop_consumes_bandwidth) =>
+            {
+                let start = state.busy_until.map_or(now, |busy| busy.max(now));
+                let transmit = packet.data.len() as f64 / bandwidth as f64;
+                let done = saturating_add(start, Duration::from_secs_f64(transmit));
+                state.busy_until = Some(done);
+                saturating_add(instant, done - now)
+            }
+            _ => instant,
+        };
+        let instant = match config.delivery_granularity {
+            Some(granularity) if !granularity.is_zero() => {
+                let elapsed = instant.saturating_duration_since(self.epoch).as_nanos();
+                let granularity = granularity.as_nanos();
+                let buckets = elapsed.div_ceil(granularity);
+                let nanos = u64::try_from(buckets * granularity).unwrap_or(u64::MAX);
+                saturating_add(self.epoch, Duration::from_nanos(nanos))
+            }
+            _ => instant,
+        };
+
+        let effects = config.effects;
+        let corruption = (effects.corruption && draws.corruption < config.corruption_chance)
+            .then(|| {
+                corruption_target(
+                    packet.data.len(),
+                    config.corruption_range.as_ref(),
+                    draws.corrupted_byte,
+                    draws.corrupted_bit,
+                )
+            })
+            .flatten();
+        let dup_ack = drop.is_none() && !state.dry_run && state.dup_acks_left > 0;
+        if dup_ack {
+            state.dup_acks_left -= 1;
+        }
+        let duplicate =
+            effects.duplication && (dup_ack || draws.duplicate < config.duplicate_chance);
+        let reorder = match config.reorder.filter(|_| effects.reorder) {
+            _ if state.held.is_some() => None,
+            Some(ReorderMode::SwapAdjacent) => Some(1),
+            // The roll is uniform below `chance`, so it also picks the depth.
+            Some(ReorderMode::Random { chance }) if draws.reorder < chance => {
+                let lookahead = config.reorder_max_lookahead.max(1);
+                let depth = 1 + (draws.reorder / chance * lookahead as f32) as usize;
+                Some(depth.min(lookahead))
+            }
+            _ => None,
+        };
+        let delay = instant.saturating_duration_since(now);
+        let delay = if clamped || delay > max_total_delay {
+            if drop.is_none() {
+                state.stats.delays_clamped += 1;
+            }
+            delay.min(max_total_delay)
+        } else {
+            delay
+        };
+        let fate = FateRecord {
+            drop,
+            delay,
+            duplicate,
+            reorder,
+            corruption,
+        };
+        (fate, draws.loss)
+    }
+
+    /// Runs a packet that was read off the socket at `arrival` through the
+    /// conditioning, queueing it unless it is dropped.
+    fn ingest(
+        &self,
+        queue: &mut TimeQueue<QueuedPacket>,
+        arrival: Instant,
+        packet: RecvFrom,
+        info: PacketInfo,
+    ) {
+        if let ConditionDecision::Deliver(deliver) = self.decide(arrival, packet, info) {
+            let mut state = lock(&self.state);
+            for (instant, queued) in deliver {
+                if self
+                    .config
+                    .max_queue_len
+                    .is_some_and(|max| queue.len() >= max)
+                {
+                    state.stats.queue_overflow_drops += 1;
+                    let (sequence, packet) = (queued.annotation.sequence, &queued.packet);
+                    state.capture(|| {
+                        CaptureRecord::dropped_on_arrival(
+                            arrival,
+                            sequence,
+                            packet.addr,
+                            packet.data.len(),
+                        )
+                    });
+                    continue;
+                }
+                self.enqueue(queue, &mut state, instant, queued);
+            }
+        }
+    }
+
+    /// Queues a packet to be delivered at `instant`, ahead of packets in the
+    /// same `dscp_priority_window` with a lower DSCP if `dscp_priority` is
+    /// set.
+    fn enqueue(
+        &self,
+        queue: &mut TimeQueue<QueuedPacket>,
+        state: &mut ConditionerState,
+        instant: Instant,
+        queued: QueuedPacket,
+    ) {
+        state.stats.packets_queued += 1;
+        state.stats.bytes_currently_queued += queued.packet.data.len() as u64;
+        if !self.config.dscp_priority {
+            queue.add_item(instant, queued);
+            return;
+        }
+        let window = self.config.dscp_priority_window.as_nanos();
+        let slot = if window == 0 {
+            instant
+        } else {
+            let elapsed = instant.saturating_duration_since(self.epoch).as_nanos();
+            let nanos = u64::try_from(elapsed - elapsed % window).unwrap_or(u64::MAX);
+            saturating_add(self.epoch, Duration::from_nanos(nanos))
+        };
+        let priority = queued.annotation.dscp.unwrap_or(0);
+        queue.add_item_in_slot(instant, slot, priority, queued);
+    }
+
+    /// Applies egress loss to a packet that came due at `due` when loss comes
+    /// after latency, counting it as delivered if it survives, see
+    /// [`Condition::condition_egress`].
+    fn release(&self, now: Instant, due: Instant, queued: &mut QueuedPacket) -> bool {
+        let arrived = due.checked_sub(queued.annotation.delay).unwrap_or(due);
+        // Only replayed packets and ones added through the queue handle
+        // haven't rolled yet. Same lock order as `decide`, the random source
+        // before the state.
+        let loss_roll = match self.config.stage_order {
+            StageOrder::LatencyThenLoss => queued
+                .loss_roll
+                .or_else(|| Some(lock(&self.random).next_f32())),
+            StageOrder::LossThenLatency => None,
+        };
+        let mut state = lock(&self.state);
+        let delivered = self.egress(now, arrived, due, queued, loss_roll, &mut state);
+        let annotation = &queued.annotation;
+        state.capture(|| CaptureRecord {
+            at: now,
+            sequence: Some(annotation.sequence),
+            direction: Direction::Inbound,
+            addr: Some(queued.packet.addr),
+            len: queued.packet.data.len(),
+            fate: match (delivered, annotation.is_duplicate) {
+                (false, _) => Fate::Dropped,
+                (true, false) => Fate::Delivered,
+                (true, true) => Fate::Duplicated,
+            },
+            delay: Some(now.saturating_duration_since(arrived)),
+        });
+        delivered
+    }
+
+    /// Egress stages of [`Conditioner::release`], `loss_roll` is only given
+    /// when loss is applied on egress.
+    fn egress(
+        &self,
+        now: Instant,
+        arrived: Instant,
+        due: Instant,
+        queued: &mut QueuedPacket,
+        loss_roll: Option<f32>,
+        state: &mut ConditionerState,
+    ) -> bool {
+        let len = queued.packet.data.len();
+        state.stats.bytes_currently_queued = state
+            .stats
+            .bytes_currently_queued
+            .saturating_sub(len as u64);
+        if let Some(max_age) = self.config.max_queue_age {
+            if now.saturating_duration_since(arrived) > max_age {
+                state.stats.packets_expired += 1;
+                return false;
+            }
+        }
+        if state.link.is_down(now) {
+            state.stats.link_down_drops += 1;
+            return false;
+        }
+        if let Some(n) = loss_roll {
+            if !self.should_keep(now, state, n) {
+                return false;
+            }
+        }
+        state.stats.packets_delivered += 1;
+        state.stats.bytes_delivered_total += len as u64;
+        queued.annotation.since_previous_delivery = state
+            .last_delivery_due
+            .map(|previous| due.saturating_duration_since(previous));
+        state.last_delivery_due = Some(due);
+        if let Some(max) = self.config.sla_max_latency {
+            if now.saturating_duration_since(arrived) > max {
+                state.stats.sla_breaches += 1;
+            }
+        }
+        true
+    }
+
+    /// Conditions a packet as if it had just been received from `addr`,
+    /// without touching the socket. A later recv delivers it once its
+    /// latency has passed, unless it is dropped.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn inject(&self, addr: SocketAddr, data: Vec<u8>) {
+        let mut queue = lock(&self.queue);
+        let now = self.clock.now();
+        self.ingest(
+            &mut queue,
+            now,
+            RecvFrom { addr, data },
+            PacketInfo::default(),
+        );
+    }
+
+    /// Like [`Conditioner::inject`], as if the socket had reported `info`
+    /// about the packet, e.g. that it was sent to a multicast group.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn inject_with_info(&self, addr: SocketAddr, info: PacketInfo, data: Vec<u8>) {
+        let mut queue = lock(&self.queue);
+        let now = self.clock.now();
+        self.ingest(&mut queue, now, RecvFrom { addr, data }, info);
+    }
+
+    /// Makes the first recv at or after `at` fail with an error of `kind`,
+    /// e.g. `ConnectionReset` to simulate the peer going away. The error is
+    /// returned once, before any packets that are due.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn inject_error(&self, kind: io::ErrorKind, at: Instant) {
+        lock(&self.state).injected_errors.add_item(at, kind);
+    }
+}
+
+impl<S, R> Conditioner<S, R>
+where
+    S: SocketLike,
+    R: RandomSource,
+{
+    /// Runs a popped packet through [`Conditioner::release`], mirroring it to
+    /// the tap if it survives.
+    fn survives_egress(&self, now: Instant, entry: &mut ItemContainer<QueuedPacket>) -> bool {
+        if !self.release(now, entry.instant, &mut entry.item) {
+            return false;
+        }
+        let tap = lock(&self.state).tap;
+        if let Some(tap) = tap {
+            let _ = self.socket.send_to(&entry.item.packet.data, tap);
+        }
+        true
+    }
+
+    /// Appends ready packets from the same source as `first` onto the `len`
+    /// bytes already in `buf`, the way Linux's UDP GRO coalesces them, and
+    /// returns how many segments the buffer ends up holding.
+    fn coalesce(
+        &self,
+        queue: &mut TimeQueue<QueuedPacket>,
+        now: Instant,
+        window: Duration,
+        first: &ItemContainer<QueuedPacket>,
+        buf: &mut [u8],
+        len: &mut usize,
+    ) -> usize {
+        let segment_size = first.item.packet.data.len();
+        let mut segments = 1;
+        if segment_size == 0 || *len < segment_size {
+            return segments;
+        }
+
+        loop {
+            match queue.peek_entry() {
+                Some(entry)
+                    if entry.instant <= now
+                        && entry.item.packet.addr == first.item.packet.addr
+                        && entry.instant.saturating_duration_since(first.instant) <= window
+                        && entry.item.packet.data.len() <= segment_size
+                        && *len + entry.item.packet.data.len() <= buf.len() => {}
+                _ => break,
+            }
+            let mut entry = match queue.pop_entry_at(now) {
+                Some(entry) => entry,
+                None => break,
+            };
+            if !self.survives_egress(now, &mut entry) {
+                continue;
+            }
+
+            let data = &entry.item.packet.data;
+            buf[*len..*len + data.len()].copy_from_slice(data);
+            *len += data.len();
+            segments += 1;
+            // Only the last segment may be shorter than the rest.
+            if data.len() < segment_size {
+                break;
+            }
+        }
+        segments
+    }
+
+    /// Blocks until a packet is delivered or `timeout` elapses, returning
+    /// `TimedOut` in the latter case.
+    ///
+    /// Packets come due on the conditioner's clock, but the timeout is always
+    /// measured in real time, so a call on a [`Clock::Mock`] that nobody
+    /// advances still returns once `timeout` is over.
+    ///
+    /// The underlying socket should be nonblocking, waiting is done by parking
+    /// the thread in short sleeps rather than an OS poll so it behaves the
+    /// same on every platform. Sleeps are at most [`BLOCKING_POLL_INTERVAL`],
+    /// though on Windows the default timer resolution may stretch them to
+    /// around 15ms.
+    pub fn recv_from_blocking(
+        &self,
+        buf: &mut [u8],
+        timeout: Option<Duration>,
+    ) -> io::Result<(usize, SocketAddr)> {
+        let deadline = timeout.map(|timeout| saturating_add(Instant::now(), timeout));
+        loop {
+            let wait = match self.recv_from_status(buf) {
+                Ok(RecvStatus::Delivered(received, addr)) => return Ok((received, addr)),
+                Ok(RecvStatus::WaitingUntil(instant)) => instant
+                    .saturating_duration_since(self.clock.now())
+                    .min(BLOCKING_POLL_INTERVAL),
+                Ok(RecvStatus::Idle) => BLOCKING_POLL_INTERVAL,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => BLOCKING_POLL_INTERVAL,
+                Err(err) => return Err(err),
+            };
+
+            if let Some(deadline) = deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(io::Error::from(io::ErrorKind::TimedOut));
+                }
+                std::thread::sleep(wait.min(deadline - now));
+            } else {
+                std::thread::sleep(wait);
+            }
+        }
+    }
+
+    /// Like `recv_from`, also returning metadata about the delivered packet.
+    pub fn recv_from_with_metadata(
+        &self,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, RecvMetadata)> {
+        match self.poll_recv(buf)? {
+            (RecvStatus::Delivered(received, addr), metadata) => Ok((received, addr, metadata)),
+            (RecvStatus::WaitingUntil(_) | RecvStatus::Idle, _) => {
+                Err(std::io::Error::from(std::io::ErrorKind::WouldBlock))
+            }
+        }
+    }
+
+    /// Like `recv_from`, also returning what the conditioner did to the
+    /// delivered packet.
+    pub fn recv_from_annotated(
+        &self,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, DeliveryAnnotation)> {
+        self.recv_from_with_metadata(buf)
+            .map(|(received, addr, metadata)| (received, addr, metadata.annotation))
+    }
+
+    /// Like `recv_from`, also returning whether the datagram was cut down to
+    /// fit `buf`. The full length is in
+    /// [`RecvMetadata::truncated_from`].
+    pub fn recv_from_trunc(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, bool)> {
+        self.recv_from_with_metadata(buf)
+            .map(|(received, addr, metadata)| (received, addr, metadata.truncated_from.is_some()))
+    }
+
+    /// Like `recv_from`, but reports why nothing was delivered instead of
+    /// returning `WouldBlock`, so event loops can schedule their next wake.
+    ///
+    /// Only returns `WouldBlock` if the queue is currently locked by another
+    /// thread.
+    pub fn recv_from_status(&self, buf: &mut [u8]) -> io::Result<RecvStatus> {
+        self.poll_recv(buf).map(|(status, _)| status)
+    }
+
+    fn poll_recv(&self, buf: &mut [u8]) -> io::Result<(RecvStatus, RecvMetadata)> {
+        if self.config.strict {
+            assert!(
+                self.is_nonblocking() != Some(false),
+                "strict mode: the conditioner was polled while its socket is blocking"
+            );
+        }
+        let injected = lock(&self.state)
+            .injected_errors
+            .pop_item_at(self.clock.now());
+        if let Some(kind) = injected {
+            return Err(io::Error::from(kind));
+        }
+        let mut queue = match self.queue.try_lock() {
+            Ok(queue) => queue,
+            Err(TryLockError::Poisoned(poisoned)) => recover(&self.queue, poisoned),
+            Err(TryLockError::WouldBlock) => {
+                return Err(std::io::Error::from(std::io::ErrorKind::WouldBlock))
+            }
+        };
+
+        let want_info = self.config.multicast.is_some() || self.config.dscp_priority;
+        if want_info {
+            let mut state = lock(&self.state);
+            if !state.packet_info {
+                self.socket.enable_packet_info()?;
+                state.packet_info = true;
+            }
+        }
+
+        let mut temp_buf = [0; 16384];
+        let mut reads = 0;
+        while reads < self.config.max_reads_per_poll.max(1) {
+            let received = if want_info {
+                self.socket.recv_from_info(&mut temp_buf)
+            } else {
+                self.socket
+                    .recv_from(&mut temp_buf)
+                    .map(|(received, addr)| (received, addr, PacketInfo::default()))
+            };
+            // Taken right after the read, so delays are measured from when
+            // the packet arrived rather than from whenever it gets conditioned.
+            let arrival = self.clock.now();
+            match received {
+                Ok((received, addr, info)) => {
+                    self.ingest(
+                        &mut queue,
+                        arrival,
+                        RecvFrom {
+                            addr,
+                            data: temp_buf[..received].to_vec(),
+                        },
+                        info,
+                    );
+                    reads += 1;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                // Nothing to read right now, a read timeout on a blocking socket
+                // shows up as either depending on the platform.
+                Err(err)
+                    if err.kind() == io::ErrorKind::WouldBlock
+                        || err.kind() == io::ErrorKind::TimedOut =>
+                {
+                    break
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        let now = self.clock.now();
+        while let Some(mut first) = queue.pop_entry_at(now) {
+            if !self.survives_egress(now, &mut first) {
+                continue;
+            }
+
+            // Same as std, excess bytes of a datagram that does not fit are discarded.
+            let packet = &first.item.packet;
+            let mut len = packet.data.len().min(buf.len());
+            buf[..len].copy_from_slice(&packet.data[..len]);
+
+            let mut metadata = RecvMetadata {
+                annotation: first.item.annotation,
+                truncated_from: Some(first.item.packet.data.len()).filter(|&full| full > len),
+                ..RecvMetadata::default()
+            };
+            if let Some(window) = self.config.gro_window {
+                if self.coalesce(&mut queue, now, window, &first, buf, &mut len) > 1 {
+                    metadata.segment_size = Some(first.item.packet.data.len());
+                }
+            }
+            return Ok((RecvStatus::Delivered(len, first.item.packet.addr), metadata));
+        }
+
+        let status = match queue.peek_entry() {
+            Some(entry) => RecvStatus::WaitingUntil(entry.instant),
+            None => RecvStatus::Idle,
+        };
+        Ok((status, RecvMetadata::default()))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::VecDeque;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::clock::MockClock;
+
+    /// What the next read from a [`MockSocket`] returns.
+    type Incoming = io::Result<(Vec<u8>, SocketAddr)>;
+
+    /// Socket fed by the test rather than the network.
+    #[derive(Debug, Default)]
+    pub(crate) struct MockSocket {
+        incoming: Mutex<VecDeque<Incoming>>,
+        pub(crate) sent: Mutex<Vec<(Vec<u8>, Option<SocketAddr>)>>,
+    }
+
+    impl MockSocket {
+        /// Queues a datagram for the conditioner to read.
+        pub(crate) fn push(&self, addr: SocketAddr, data: Vec<u8>) {
+            lock(&self.incoming).push_back(Ok((data, addr)));
+        }
+
+        /// Queues an error for the conditioner to read.
+        pub(crate) fn push_error(&self, kind: io::ErrorKind) {
+            lock(&self.incoming).push_back(Err(kind.into()));
+        }
+    }
+
+    impl SocketLike for MockSocket {
+        fn set_nonblocking(&self, _nonblocking: bool) -> io::Result<()> {
+            Ok(())
+        }
+        fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+            let (data, addr) = lock(&self.incoming)
+                .pop_front()
+                .unwrap_or_else(|| Err(io::ErrorKind::WouldBlock.into()))?;
+            let len = data.len().min(buf.len());
+            buf[..len].copy_from_slice(&data[..len]);
+            Ok((len, addr))
+        }
+        fn send(&self, buf: &[u8]) -> io::Result<usize> {
+            lock(&self.sent).push((buf.to_vec(), None));
+            Ok(buf.len())
+        }
+        fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+            lock(&self.sent).push((buf.to_vec(), Some(addr)));
+            Ok(buf.len())
+        }
+    }
+
+    pub(crate) fn peer(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    /// Conditioner over a [`MockSocket`] with a fixed seed and a mock clock.
+    pub(crate) fn conditioner(config: ConditionerConfig) -> Conditioner<MockSocket, StdRng> {
+        let mut conditioner = Conditioner::with_seed(config, MockSocket::default(), 0);
+        conditioner.set_clock(Clock::Mock(MockClock::new()));
+        conditioner
+    }
+
+    /// Every packet that is due, in delivery order.
+    pub(crate) fn recv_all<S: SocketLike, R: RandomSource>(
+        conditioner: &Conditioner<S, R>,
+    ) -> Vec<(Vec<u8>, DeliveryAnnotation)> {
+        let mut delivered = Vec::new();
+        let mut buf = [0; 2048];
+        while let Ok((len, _, annotation)) = conditioner.recv_from_annotated(&mut buf) {
+            delivered.push((buf[..len].to_vec(), annotation));
+        }
+        delivered
+    }
+
+    #[test]
+    fn blocking_recv_times_out_on_a_frozen_mock_clock() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        conditioner.inject(peer(1), vec![1]);
+
+        let started = Instant::now();
+        let err = conditioner
+            .recv_from_blocking(&mut [0; 16], Some(Duration::from_millis(50)))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn blocking_recv_waits_for_the_mock_clock() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let conditioner = Arc::new(conditioner(config));
+        conditioner.inject(peer(1), vec![7]);
+
+        let advancer = Arc::clone(&conditioner);
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            advancer.advance(Duration::from_millis(10));
+        });
+        let mut buf = [0; 16];
+        let (len, addr) = conditioner
+            .recv_from_blocking(&mut buf, Some(Duration::from_secs(5)))
+            .unwrap();
+        handle.join().unwrap();
+        assert_eq!((&buf[..len], addr), (&[7][..], peer(1)));
+    }
+
+    fn inject_dscp<S, R: RandomSource>(conditioner: &Conditioner<S, R>, dscp: u8) {
+        let info = PacketInfo {
+            dscp: Some(dscp),
+            ..PacketInfo::default()
+        };
+        conditioner.inject_with_info(peer(1), info, vec![dscp]);
+    }
+
+    #[test]
+    fn dscp_priority_within_window() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(10))
+            .dscp_priority(true)
+            .dscp_priority_window(Duration::from_millis(1))
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        inject_dscp(&conditioner, 0);
+        conditioner.advance(Duration::from_micros(1));
+        inject_dscp(&conditioner, 46);
+
+        conditioner.advance(Duration::from_millis(10));
+        let delivered: Vec<_> = recv_all(&conditioner)
+            .into_iter()
+            .map(|(data, _)| data)
+            .collect();
+        assert_eq!(delivered, [[46], [0]]);
+    }
+
+    #[test]
+    fn dscp_priority_without_window_needs_equal_instants() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(10))
+            .dscp_priority(true)
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        inject_dscp(&conditioner, 0);
+        inject_dscp(&conditioner, 46);
+        conditioner.advance(Duration::from_micros(1));
+        inject_dscp(&conditioner, 0);
+        inject_dscp(&conditioner, 46);
+
+        conditioner.advance(Duration::from_millis(10));
+        let delivered: Vec<_> = recv_all(&conditioner)
+            .into_iter()
+            .map(|(data, _)| data)
+            .collect();
+        assert_eq!(delivered, [[46], [0], [46], [0]]);
+    }
+
+    /// Packets out of 100 lossy ones queued at once that come through a
+    /// 1000 B/s link within 3s.
+    fn delivered_in_3s(drop_consumes_bandwidth: bool) -> usize {
+        let config = ConditionerConfig::builder()
+            .bandwidth(1000)
+            .packet_loss_ratio(0.5)
+            .drop_consumes_bandwidth(drop_consumes_bandwidth)
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        for _ in 0..100 {
+            conditioner.inject(peer(1), vec![0; 100]);
+        }
+        conditioner.advance(Duration::from_secs(3));
+        recv_all(&conditioner).len()
+    }
+
+    #[test]
+    fn drops_consume_bandwidth_only_if_configured() {
+        // Every kept packet takes 100ms of the link.
+        assert_eq!(delivered_in_3s(false), 30);
+        let consumed = delivered_in_3s(true);
+        assert!((10..=20).contains(&consumed), "{}", consumed);
+    }
+
+    #[test]
+    fn recv_status_tells_idle_from_waiting() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        let mut buf = [0; 16];
+        assert_eq!(
+            conditioner.recv_from_status(&mut buf).unwrap(),
+            RecvStatus::Idle
+        );
+
+        let due = conditioner.clock().now() + Duration::from_millis(10);
+        conditioner.inject(peer(1), vec![1, 2]);
+        assert_eq!(
+            conditioner.recv_from_status(&mut buf).unwrap(),
+            RecvStatus::WaitingUntil(due)
+        );
+
+        conditioner.advance(Duration::from_millis(10));
+        assert_eq!(
+            conditioner.recv_from_status(&mut buf).unwrap(),
+            RecvStatus::Delivered(2, peer(1))
+        );
+        assert_eq!(
+            conditioner.recv_from_status(&mut buf).unwrap(),
+            RecvStatus::Idle
+        );
+    }
+
+    #[test]
+    fn truncates_like_a_real_socket() {
+        use rand::Rng;
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let raw = UdpSocket::bind("127.0.0.1:0").unwrap();
+        raw.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let conditioner = Conditioner::bind(ConditionerConfig::default(), "127.0.0.1:0").unwrap();
+        conditioner.set_nonblocking(true).unwrap();
+        let conditioned = conditioner.local_addr().unwrap();
+
+        let mut rng = StdRng::seed_from_u64(105);
+        for _ in 0..50 {
+            let payload: Vec<u8> = (0..rng.gen_range(0..64)).map(|_| rng.gen()).collect();
+            let buf_len = rng.gen_range(0..64);
+            sender.send_to(&payload, raw.local_addr().unwrap()).unwrap();
+            sender.send_to(&payload, conditioned).unwrap();
+
+            let mut expected = vec![0; buf_len];
+            let (expected_len, _) = raw.recv_from(&mut expected).unwrap();
+            let mut actual = vec![0; buf_len];
+            let started = Instant::now();
+            let (actual_len, _) = loop {
+                match conditioner.recv_from(&mut actual) {
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                        assert!(started.elapsed() < Duration::from_secs(5));
+                        std::thread::sleep(Duration::from_millis(1));
+                    }
+                    result => break result.unwrap(),
+                }
+            };
+            assert_eq!(actual_len, expected_len);
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn advance_delivers_due_packets() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        conditioner.inject(peer(1), vec![1]);
+        conditioner.advance(Duration::from_millis(99));
+        assert!(recv_all(&conditioner).is_empty());
+        conditioner.advance(Duration::from_millis(1));
+        assert_eq!(recv_all(&conditioner).len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Conditioner::advance requires a Clock::Mock")]
+    fn advance_needs_a_mock_clock() {
+        Conditioner::new(ConditionerConfig::default(), MockSocket::default())
+            .advance(Duration::from_millis(1));
+    }
+
+    #[test]
+    fn binds_on_interface() {
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        let conditioner =
+            Conditioner::bind_on_interface(ConditionerConfig::default(), ip, 0).unwrap();
+        assert_eq!(conditioner.local_addr().unwrap().ip(), ip);
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        conditioner
+            .send_to(&[1], receiver.local_addr().unwrap())
+            .unwrap();
+        let (_, source) = receiver.recv_from(&mut [0; 16]).unwrap();
+        assert_eq!(source, conditioner.local_addr().unwrap());
+    }
+
+    #[test]
+    fn rejects_sends_over_max_send_size() {
+        let config = ConditionerConfig::builder()
+            .max_send_size(4)
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        assert_eq!(conditioner.send_to(&[0; 4], peer(1)).unwrap(), 4);
+        let err = conditioner.send_to(&[0; 5], peer(1)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            err.to_string(),
+            "payload of 5 bytes exceeds max_send_size of 4 bytes"
+        );
+        assert!(conditioner.send(&[0; 5]).is_err());
+        assert_eq!(lock(&conditioner.socket.sent).len(), 1);
+    }
+
+    #[test]
+    fn black_holes_large_sends_only() {
+        let config = ConditionerConfig::builder()
+            .send_black_hole_size(4)
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        assert_eq!(conditioner.send_to(&[0; 5], peer(1)).unwrap(), 5);
+        assert_eq!(conditioner.send(&[0; 5]).unwrap(), 5);
+        assert_eq!(conditioner.send_to(&[1; 4], peer(1)).unwrap(), 4);
+        assert_eq!(
+            *lock(&conditioner.socket.sent),
+            [(vec![1; 4], Some(peer(1)))]
+        );
+
+        conditioner.inject(peer(1), vec![2; 5]);
+        assert_eq!(recv_all(&conditioner)[0].0, [2; 5]);
+    }
+
+    #[test]
+    fn black_hole_checks_gso_segments() {
+        let sent = |segment_size| {
+            let config = ConditionerConfig::builder()
+                .gso_segment_size(segment_size)
+                .send_black_hole_size(4)
+                .build()
+                .unwrap();
+            let conditioner = conditioner(config);
+            assert_eq!(conditioner.send_to(&[0; 10], peer(1)).unwrap(), 10);
+            let sent = lock(&conditioner.socket.sent).len();
+            sent
+        };
+        assert_eq!(sent(4), 3);
+        assert_eq!(sent(5), 0);
+    }
+
+    #[test]
+    fn inspect_queue_leaves_packets_queued() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        conditioner.inject(peer(1), vec![1]);
+        conditioner.inject(peer(2), vec![2]);
+
+        let queued = conditioner.inspect_queue();
+        assert_eq!(queued.len(), 2);
+        assert_eq!((queued[0].addr, &queued[0].data[..]), (peer(1), &[1][..]));
+        assert_eq!((queued[1].addr, &queued[1].data[..]), (peer(2), &[2][..]));
+
+        conditioner.advance(Duration::from_millis(10));
+        let delivered: Vec<_> = recv_all(&conditioner)
+            .into_iter()
+            .map(|(data, _)| data)
+            .collect();
+        assert_eq!(delivered, [[1], [2]]);
+        assert!(conditioner.inspect_queue().is_empty());
+    }
+
+    /// Variance of the difference between consecutive packets' delays.
+    fn successive_delay_variance(jitter_correlation: f32) -> f64 {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(100))
+            .jitter(Duration::from_millis(50))
+            .jitter_correlation(jitter_correlation)
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        for _ in 0..200 {
+            conditioner.inject(peer(1), vec![0]);
+        }
+        conditioner.advance(Duration::from_secs(1));
+        let mut delivered = recv_all(&conditioner);
+        delivered.sort_by_key(|(_, annotation)| annotation.sequence);
+        let diffs: Vec<f64> = delivered
+            .windows(2)
+            .map(|pair| pair[1].1.delay.as_secs_f64() - pair[0].1.delay.as_secs_f64())
+            .collect();
+        let mean = diffs.iter().sum::<f64>() / diffs.len() as f64;
+        diffs.iter().map(|diff| (diff - mean).powi(2)).sum::<f64>() / diffs.len() as f64
+    }
+
+    #[test]
+    fn jitter_correlation_smooths_delays() {
+        assert!(successive_delay_variance(0.9) < successive_delay_variance(0.0) / 4.0);
+    }
+
+    #[test]
+    fn recv_with_metadata_matches_recv_from() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(10))
+            .jitter(Duration::from_millis(5))
+            .build()
+            .unwrap();
+        let plain = conditioner(config.clone());
+        let with_metadata = conditioner(config);
+        for n in 0..10 {
+            plain.inject(peer(n), vec![n as u8; 3]);
+            with_metadata.inject(peer(n), vec![n as u8; 3]);
+        }
+        plain.advance(Duration::from_millis(20));
+        with_metadata.advance(Duration::from_millis(20));
+
+        let (mut expected, mut actual) = ([0; 16], [0; 16]);
+        for _ in 0..10 {
+            let (len, addr) = plain.recv_from(&mut expected).unwrap();
+            let (metadata_len, metadata_addr, metadata) =
+                with_metadata.recv_from_with_metadata(&mut actual).unwrap();
+            assert_eq!((metadata_len, metadata_addr), (len, addr));
+            assert_eq!(actual[..len], expected[..len]);
+            assert_eq!(metadata.segment_size, None);
+        }
+        assert!(with_metadata.recv_from_with_metadata(&mut actual).is_err());
+    }
+
+    #[test]
+    fn disable_env_var_replaces_bound_config() {
+        let lossy = ConditionerConfig::builder()
+            .packet_loss_ratio(1.0)
+            .build()
+            .unwrap();
+        assert_eq!(unless_disabled(lossy.clone(), None), lossy);
+        assert_eq!(unless_disabled(lossy.clone(), Some("0")), lossy);
+        assert_eq!(
+            unless_disabled(lossy, Some("1")),
+            ConditionerConfig::default()
+        );
+    }
+
+    /// Stats after 10 packets went through a link dropping all of them.
+    fn lossy_stats(stage_order: StageOrder) -> (usize, ConditionerStats) {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(10))
+            .packet_loss_ratio(1.0)
+            .stage_order(stage_order)
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        for _ in 0..10 {
+            conditioner.inject(peer(1), vec![0]);
+        }
+        let queued = conditioner.inspect_queue().len();
+        conditioner.advance(Duration::from_millis(10));
+        assert!(recv_all(&conditioner).is_empty());
+        (queued, conditioner.stats())
+    }
+
+    #[test]
+    fn loss_then_latency_keeps_drops_out_of_the_queue() {
+        let (queued, stats) = lossy_stats(StageOrder::LossThenLatency);
+        assert_eq!(queued, 0);
+        assert_eq!(stats.packets_queued, 0);
+        assert_eq!(stats.random_loss_drops, 10);
+    }
+
+    #[test]
+    fn latency_then_loss_queues_then_drops() {
+        let (queued, stats) = lossy_stats(StageOrder::LatencyThenLoss);
+        assert_eq!(queued, 10);
+        assert_eq!(stats.packets_queued, 10);
+        assert_eq!(stats.random_loss_drops, 10);
+        assert_eq!(stats.queue_len, 0);
+    }
+
+    #[test]
+    fn injected_packet_arrives_after_latency() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(30))
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        conditioner.inject(peer(7), b"hello".to_vec());
+
+        let mut buf = [0; 16];
+        assert!(conditioner.recv_from(&mut buf).is_err());
+        conditioner.advance(Duration::from_millis(30));
+        assert_eq!(conditioner.recv_from(&mut buf).unwrap(), (5, peer(7)));
+        assert_eq!(&buf[..5], b"hello");
+        assert!(lock(&conditioner.socket.sent).is_empty());
+        assert_eq!(conditioner.stats().packets_received, 1);
+    }
+
+    #[test]
+    fn gro_coalesces_within_window() {
+        let config = ConditionerConfig::builder()
+            .gro_window(Duration::from_millis(5))
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        let ms = Duration::from_millis(1);
+        conditioner.inject(peer(1), vec![1, 1]);
+        conditioner.advance(ms);
+        conditioner.inject(peer(1), vec![2, 2]);
+        conditioner.advance(ms);
+        conditioner.inject(peer(1), vec![3]);
+        conditioner.inject(peer(1), vec![4, 4]);
+        conditioner.advance(10 * ms);
+        conditioner.inject(peer(1), vec![5, 5]);
+        conditioner.inject(peer(2), vec![6, 6]);
+
+        let mut buf = [0; 16];
+        let (len, addr, metadata) = conditioner.recv_from_with_metadata(&mut buf).unwrap();
+        assert_eq!((&buf[..len], addr), (&[1, 1, 2, 2, 3][..], peer(1)));
+        assert_eq!(metadata.segment_size, Some(2));
+
+        // Only whole segments fit in the rest of the buffer.
+        let (len, _, metadata) = conditioner.recv_from_with_metadata(&mut buf[..3]).unwrap();
+        assert_eq!(&buf[..len], [4, 4]);
+        assert_eq!(metadata.segment_size, None);
+
+        let (len, _, _) = conditioner.recv_from_with_metadata(&mut buf).unwrap();
+        assert_eq!(&buf[..len], [5, 5]);
+        let (len, addr, _) = conditioner.recv_from_with_metadata(&mut buf).unwrap();
+        assert_eq!((&buf[..len], addr), (&[6, 6][..], peer(2)));
+    }
+
+    #[test]
+    fn gso_splits_sends() {
+        let config = ConditionerConfig::builder()
+            .gso_segment_size(2)
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        assert_eq!(conditioner.send_to(&[1, 1, 2, 2, 3], peer(1)).unwrap(), 5);
+        let sent: Vec<_> = lock(&conditioner.socket.sent)
+            .iter()
+            .map(|(data, _)| data.clone())
+            .collect();
+        assert_eq!(sent, [vec![1, 1], vec![2, 2], vec![3]]);
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let conditioner = Arc::new(conditioner(ConditionerConfig::default()));
+        let threads: Vec<_> = (0..4)
+            .map(|port| {
+                let conditioner = Arc::clone(&conditioner);
+                std::thread::spawn(move || {
+                    for _ in 0..10 {
+                        conditioner.inject(peer(port), vec![0]);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(recv_all(&conditioner).len(), 40);
+        assert_eq!(conditioner.stats().packets_delivered, 40);
+    }
+
+    #[test]
+    fn delay_fn_overrides_latency() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_secs(1))
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        conditioner.set_delay_fn(|packet| Duration::from_millis(10 * packet.data[0] as u64));
+        for n in [3, 1, 2] {
+            conditioner.inject(peer(1), vec![n]);
+        }
+
+        conditioner.advance(Duration::from_millis(20));
+        let delivered: Vec<_> = recv_all(&conditioner)
+            .into_iter()
+            .map(|(data, annotation)| (data[0], annotation.delay))
+            .collect();
+        assert_eq!(
+            delivered,
+            [
+                (1, Duration::from_millis(10)),
+                (2, Duration::from_millis(20))
+            ]
+        );
+
+        conditioner.clear_delay_fn();
+        conditioner.inject(peer(1), vec![0]);
+        conditioner.advance(Duration::from_millis(10));
+        assert_eq!(recv_all(&conditioner).len(), 1);
+        conditioner.advance(Duration::from_secs(1));
+        assert_eq!(recv_all(&conditioner).len(), 1);
+    }
+
+    #[test]
+    fn dry_run_counts_without_applying() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(50))
+            .packet_loss_ratio(1.0)
+            .corruption_chance(1.0)
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        conditioner.set_dry_run(true);
+        for n in 0..5 {
+            conditioner.inject(peer(1), vec![n]);
+        }
+
+        let delivered: Vec<_> = recv_all(&conditioner)
+            .into_iter()
+            .map(|(data, _)| data)
+            .collect();
+        assert_eq!(delivered, [[0], [1], [2], [3], [4]]);
+        let stats = conditioner.stats();
+        assert_eq!(stats.would_drop, 5);
+        assert_eq!(stats.would_delay, Duration::from_millis(250));
+        assert_eq!(stats.total_drops(), 0);
+        assert_eq!(stats.packets_corrupted, 0);
+
+        conditioner.set_dry_run(false);
+        conditioner.inject(peer(1), vec![5]);
+        conditioner.advance(Duration::from_millis(50));
+        assert!(recv_all(&conditioner).is_empty());
+        assert_eq!(conditioner.stats().random_loss_drops, 1);
+    }
+
+    #[test]
+    fn retries_interrupted_reads() {
+        let conditioner = conditioner(ConditionerConfig::default());
+        conditioner.socket.push_error(io::ErrorKind::Interrupted);
+        conditioner.socket.push(peer(1), vec![1]);
+        let mut buf = [0; 16];
+        assert_eq!(conditioner.recv_from(&mut buf).unwrap(), (1, peer(1)));
+    }
+
+    #[test]
+    fn surfaces_socket_errors() {
+        let conditioner = conditioner(ConditionerConfig::default());
+        conditioner
+            .socket
+            .push_error(io::ErrorKind::ConnectionReset);
+        let mut buf = [0; 16];
+        let err = conditioner.recv_from(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        let err = conditioner.recv_from(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn swap_adjacent_reorders_pairs() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(10))
+            .reorder(ReorderMode::SwapAdjacent)
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        for n in 1..=4 {
+            conditioner.inject(peer(1), vec![n]);
+            conditioner.advance(Duration::from_millis(1));
+        }
+        conditioner.advance(Duration::from_millis(10));
+        let delivered: Vec<_> = recv_all(&conditioner)
+            .into_iter()
+            .map(|(data, annotation)| (data[0], annotation.was_reordered))
+            .collect();
+        assert_eq!(delivered, [(2, false), (1, true), (4, false), (3, true)]);
+        assert_eq!(conditioner.stats().packets_reordered, 2);
+    }
+
+    #[test]
+    fn allowlist_discards_other_sources() {
+        let conditioner = conditioner(ConditionerConfig::default());
+        conditioner.set_source_allowlist(vec!["127.0.0.0/8".parse().unwrap()]);
+        conditioner.inject(peer(1), vec![1]);
+        conditioner.inject(SocketAddr::from(([10, 0, 0, 1], 1)), vec![2]);
+
+        let delivered: Vec<_> = recv_all(&conditioner)
+            .into_iter()
+            .map(|(data, _)| data)
+            .collect();
+        assert_eq!(delivered, [[1]]);
+        let stats = conditioner.stats();
+        assert_eq!((stats.packets_received, stats.packets_filtered), (1, 1));
+
+        conditioner.clear_source_allowlist();
+        conditioner.inject(SocketAddr::from(([10, 0, 0, 1], 1)), vec![3]);
+        assert_eq!(recv_all(&conditioner).len(), 1);
+    }
+
+    #[test]
+    fn peeks_next_ready_source() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        assert_eq!(conditioner.peek_ready_addr(), None);
+        conditioner.inject(peer(3), vec![1]);
+        assert_eq!(conditioner.peek_ready_addr(), None);
+
+        conditioner.advance(Duration::from_millis(10));
+        assert_eq!(conditioner.peek_ready_addr(), Some(peer(3)));
+        assert_eq!(conditioner.peek_ready_addr(), Some(peer(3)));
+        assert_eq!(conditioner.recv_from(&mut [0; 16]).unwrap(), (1, peer(3)));
+        assert_eq!(conditioner.peek_ready_addr(), None);
+    }
+
+    #[test]
+    fn rewrapping_keeps_local_addr() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let conditioner = Conditioner::new(ConditionerConfig::default(), socket);
+        conditioner.inject(peer(1), vec![1]);
+        assert_eq!(conditioner.local_addr().unwrap(), addr);
+
+        let socket = conditioner.into_socket();
+        assert_eq!(socket.local_addr().unwrap(), addr);
+        let conditioner = Conditioner::new(ConditionerConfig::default(), socket);
+        assert_eq!(conditioner.local_addr().unwrap(), addr);
+        assert!(conditioner.inspect_queue().is_empty());
+    }
+
+    /// Standard deviation of the delay through one or three chained
+    /// conditioners with equal jitter, in milliseconds.
+    fn chained_delay_std_dev(three_hops: bool) -> f64 {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(50))
+            .jitter(Duration::from_millis(20))
+            .max_reads_per_poll(usize::MAX)
+            .build()
+            .unwrap();
+        let clock = MockClock::new();
+        fn hop<S>(
+            config: &ConditionerConfig,
+            clock: &MockClock,
+            socket: S,
+            seed: u64,
+        ) -> Conditioner<S, StdRng> {
+            let mut hop = Conditioner::with_seed(config.clone(), socket, seed);
+            hop.set_clock(Clock::Mock(clock.clone()));
+            hop
+        }
+        let first = hop(&config, &clock, MockSocket::default(), 0);
+        for _ in 0..2000 {
+            first.inject(peer(1), vec![0]);
+        }
+        let last: Box<dyn SocketLike> = if three_hops {
+            let second = hop(&config, &clock, first, 1);
+            Box::new(hop(&config, &clock, second, 2))
+        } else {
+            Box::new(first)
+        };
+
+        let mut delays = Vec::new();
+        let mut buf = [0; 16];
+        for elapsed in 0..=240 {
+            while last.recv_from(&mut buf).is_ok() {
+                delays.push(elapsed as f64);
+            }
+            clock.advance(Duration::from_millis(1));
+        }
+        assert_eq!(delays.len(), 2000);
+        let mean = delays.iter().sum::<f64>() / delays.len() as f64;
+        let variance = delays
+            .iter()
+            .map(|delay| (delay - mean).powi(2))
+            .sum::<f64>()
+            / delays.len() as f64;
+        variance.sqrt()
+    }
+
+    #[test]
+    fn chained_jitter_variances_add() {
+        let ratio = chained_delay_std_dev(true) / chained_delay_std_dev(false);
+        assert!((ratio - 3f64.sqrt()).abs() < 0.15, "{}", ratio);
+    }
+
+    #[test]
+    fn min_delay_floors_jitter() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(5))
+            .jitter(Duration::from_millis(5))
+            .min_delay(Duration::from_millis(5))
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        for _ in 0..100 {
+            conditioner.inject(peer(1), vec![0]);
+        }
+        conditioner.advance(Duration::from_micros(4999));
+        assert!(recv_all(&conditioner).is_empty());
+        conditioner.advance(Duration::from_millis(10));
+        let delivered = recv_all(&conditioner);
+        assert_eq!(delivered.len(), 100);
+        assert!(delivered
+            .iter()
+            .all(|(_, annotation)| annotation.delay >= Duration::from_millis(5)));
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn log_summary_emits_labeled_info_events() {
+        use tracing::{
+            field::{Field, Visit},
+            span, Event, Level, Metadata, Subscriber,
+        };
+
+        /// Level, label and message of every event seen.
+        type Events = Arc<Mutex<Vec<(Level, String, String)>>>;
+
+        struct Collect(Events);
+
+        #[derive(Default)]
+        struct Fields {
+            label: String,
+            message: String,
+        }
+
+        impl Visit for Fields {
+            fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                match field.name() {
+                    "label" => self.label = format!("{:?}", value),
+                    "message" => self.message = format!("{:?}", value),
+                    _ => {}
+                }
+            }
+        }
+
+        impl Subscriber for Collect {
+            fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, _span: &span::Attributes<'_>) -> span::Id {
+                span::Id::from_u64(1)
+            }
+            fn record(&self, _span: &span::Id, _values: &span::Record<'_>) {}
+            fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+            fn event(&self, event: &Event<'_>) {
+                let mut fields = Fields::default();
+                event.record(&mut fields);
+                let level = *event.metadata().level();
+                lock(&self.0).push((level, fields.label, fields.message));
+            }
+            fn enter(&self, _span: &span::Id) {}
+            fn exit(&self, _span: &span::Id) {}
+        }
+
+        let conditioner = conditioner(ConditionerConfig::default());
+        conditioner.set_label("client");
+        conditioner.inject(peer(1), vec![0]);
+        let events = Events::default();
+        tracing::subscriber::with_default(Collect(Arc::clone(&events)), || {
+            conditioner.log_summary()
+        });
+
+        let events = lock(&events);
+        let summary = conditioner.stats().summary();
+        assert_eq!(events.len(), summary.lines().count());
+        for ((level, label, message), line) in events.iter().zip(summary.lines()) {
+            assert_eq!((level, label.as_str()), (&Level::INFO, "client"));
+            assert_eq!(message, line);
+        }
+        assert!(events
+            .iter()
+            .any(|(_, _, message)| message.starts_with("drops: 0 total")));
+    }
+
+    #[test]
+    fn link_down_mid_stream() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        conditioner.inject(peer(1), vec![1]);
+        conditioner.set_link_down(true);
+        conditioner.inject(peer(1), vec![2]);
+        assert_eq!(conditioner.send_to(&[3], peer(1)).unwrap(), 1);
+        conditioner.advance(Duration::from_millis(10));
+        assert!(recv_all(&conditioner).is_empty());
+        assert!(lock(&conditioner.socket.sent).is_empty());
+        assert_eq!(conditioner.stats().link_down_drops, 3);
+
+        conditioner.set_link_down(false);
+        conditioner.inject(peer(1), vec![4]);
+        conditioner.advance(Duration::from_millis(10));
+        let delivered: Vec<_> = recv_all(&conditioner)
+            .into_iter()
+            .map(|(data, _)| data)
+            .collect();
+        assert_eq!(delivered, [[4]]);
+    }
+
+    #[test]
+    fn link_down_for_comes_back_up() {
+        let config = ConditionerConfig::builder()
+            .link_down_send_error(true)
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        conditioner.link_down_for(Duration::from_millis(100));
+        let err = conditioner.send_to(&[1], peer(1)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NetworkDown);
+        conditioner.inject(peer(1), vec![1]);
+        assert!(recv_all(&conditioner).is_empty());
+
+        conditioner.advance(Duration::from_millis(100));
+        assert!(conditioner.send_to(&[2], peer(1)).is_ok());
+        conditioner.inject(peer(1), vec![2]);
+        assert_eq!(recv_all(&conditioner).len(), 1);
+    }
+
+    #[test]
+    fn delivery_instants_fall_on_granularity() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(5))
+            .jitter(Duration::from_millis(3))
+            .delivery_granularity(Duration::from_millis(4))
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        let epoch = conditioner.clock().now();
+        for _ in 0..50 {
+            conditioner.inject(peer(1), vec![0]);
+            conditioner.advance(Duration::from_micros(700));
+        }
+
+        let mut queue = lock(&conditioner.queue).clone();
+        assert!(!queue.is_empty());
+        while let Some(entry) = queue.pop_next_entry() {
+            let since_epoch = entry.instant.duration_since(epoch);
+            assert_eq!(since_epoch.as_nanos() % 4_000_000, 0, "{:?}", since_epoch);
+            assert!(since_epoch >= entry.item.annotation.delay);
+        }
+    }
+
+    #[test]
+    fn pending_reports_next_delivery() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(30))
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        assert_eq!(conditioner.pending(), (0, None));
+        conditioner.inject(peer(1), vec![0]);
+        assert_eq!(conditioner.pending(), (1, Some(Duration::from_millis(30))));
+        conditioner.advance(Duration::from_millis(40));
+        assert_eq!(conditioner.pending(), (1, Some(Duration::ZERO)));
+    }
+
+    #[test]
+    fn recovers_from_panicking_delay_fn() {
+        let conditioner = conditioner(ConditionerConfig::default());
+        conditioner.set_delay_fn(|packet| {
+            assert_ne!(packet.data, [1], "bad packet");
+            Duration::ZERO
+        });
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            conditioner.inject(peer(1), vec![1]);
+        }));
+        assert!(panicked.is_err());
+
+        conditioner.inject(peer(1), vec![2]);
+        let delivered = recv_all(&conditioner);
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0].0, [2]);
+    }
+
+    fn strict() -> ConditionerConfig {
+        ConditionerConfig::builder().strict(true).build().unwrap()
+    }
+
+    #[test]
+    fn strict_accepts_socket_made_nonblocking_before_wrapping() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let conditioner = Conditioner::new(strict(), socket);
+        let mut buf = [0; 16];
+        let err = conditioner.recv_from(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn strict_accepts_socket_that_cannot_tell_its_mode() {
+        let conditioner = Conditioner::new(strict(), MockSocket::default());
+        assert_eq!(conditioner.is_nonblocking(), None);
+        assert!(recv_all(&conditioner).is_empty());
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "strict mode: the conditioner was polled while its socket is blocking"
+    )]
+    fn strict_rejects_blocking_socket() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let conditioner = Conditioner::new(strict(), socket);
+        let _ = conditioner.recv_from(&mut [0; 16]);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "strict mode: the conditioner was polled while its socket is blocking"
+    )]
+    fn strict_rejects_socket_set_blocking_through_conditioner() {
+        let conditioner = Conditioner::new(strict(), MockSocket::default());
+        conditioner.set_nonblocking(false).unwrap();
+        let _ = conditioner.recv_from(&mut [0; 16]);
+    }
+
+    struct OutOfRange;
+
+    impl RandomSource for OutOfRange {
+        fn next_f32(&mut self) -> f32 {
+            1.5
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "strict mode: random source returned 1.5, outside of 0.0..1.0")]
+    fn strict_rejects_random_out_of_range() {
+        let conditioner = Conditioner::with_random(strict(), MockSocket::default(), OutOfRange);
+        conditioner.inject(peer(1), vec![0]);
+    }
+
+    #[test]
+    fn poll_drains_socket_up_to_cap() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(10))
+            .max_reads_per_poll(64)
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        for n in 0..50 {
+            conditioner.socket.push(peer(1), vec![n]);
+        }
+        assert!(conditioner.recv_from(&mut [0; 16]).is_err());
+        assert_eq!(conditioner.pending().0, 50);
+
+        conditioner.advance(Duration::from_millis(10));
+        let delivered: Vec<_> = recv_all(&conditioner)
+            .into_iter()
+            .map(|(data, _)| data[0])
+            .collect();
+        assert_eq!(delivered, (0..50).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn poll_reads_once_by_default() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        for n in 0..3 {
+            conditioner.socket.push(peer(1), vec![n]);
+        }
+        assert!(conditioner.recv_from(&mut [0; 16]).is_err());
+        assert_eq!(conditioner.pending().0, 1);
+    }
+
+    #[test]
+    fn annotates_duplicates_and_corruption() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(10))
+            .duplicate_chance(1.0)
+            .build()
+            .unwrap();
+        let duplicating = conditioner(config);
+        duplicating.inject(peer(1), vec![1]);
+        duplicating.advance(Duration::from_millis(10));
+        let delivered = recv_all(&duplicating);
+        assert_eq!(delivered.len(), 2);
+        let (original, copy) = (&delivered[0].1, &delivered[1].1);
+        assert!(!original.is_duplicate && copy.is_duplicate);
+        assert_eq!(original.sequence, copy.sequence);
+        assert_eq!(original.delay, Duration::from_millis(10));
+        assert!(!original.is_corrupted && !original.was_reordered);
+
+        let config = ConditionerConfig::builder()
+            .corruption_chance(1.0)
+            .build()
+            .unwrap();
+        let corrupting = conditioner(config);
+        corrupting.inject(peer(1), vec![0; 4]);
+        let delivered = recv_all(&corrupting);
+        assert!(delivered[0].1.is_corrupted);
+        assert_ne!(delivered[0].0, [0; 4]);
+    }
+
+    #[test]
+    fn config_scope_reverts_on_drop() {
+        let mut conditioner = conditioner(ConditionerConfig::default());
+        let harsh = ConditionerConfig::builder()
+            .packet_loss_ratio(1.0)
+            .build()
+            .unwrap();
+        {
+            let mut scope = conditioner.with_config_scope(harsh.clone());
+            scope.inject(peer(1), vec![1]);
+            assert!(recv_all(&scope).is_empty());
+            scope.config = ConditionerConfig::chaos();
+        }
+        assert_eq!(conditioner.config, ConditionerConfig::default());
+        conditioner.inject(peer(1), vec![2]);
+        assert_eq!(recv_all(&conditioner).len(), 1);
+        assert_eq!(conditioner.stats().random_loss_drops, 1);
+    }
+
+    #[test]
+    fn first_packet_from_a_peer_waits_longer() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(10))
+            .first_packet_extra_latency(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        conditioner.inject(peer(1), vec![1]);
+        conditioner.inject(peer(1), vec![2]);
+        assert_eq!(conditioner.peer_count(), 1);
+
+        conditioner.advance(Duration::from_millis(10));
+        let delivered = recv_all(&conditioner);
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0].0, vec![2]);
+        assert_eq!(delivered[0].1.delay, Duration::from_millis(10));
+
+        conditioner.advance(Duration::from_millis(50));
+        let delivered = recv_all(&conditioner);
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0].0, vec![1]);
+        assert_eq!(delivered[0].1.delay, Duration::from_millis(60));
+
+        conditioner.clear_peers();
+        assert_eq!(conditioner.peer_count(), 0);
+        conditioner.inject(peer(1), vec![3]);
+        conditioner.advance(Duration::from_millis(10));
+        assert!(recv_all(&conditioner).is_empty());
+    }
+
+    #[test]
+    fn drain_into_keeps_remaining_delays() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let source = conditioner(config);
+        source.inject(peer(1), vec![1]);
+        source.advance(Duration::from_millis(20));
+        source.inject(peer(1), vec![2]);
+        source.advance(Duration::from_millis(10));
+
+        let target = conditioner(ConditionerConfig::default());
+        source.drain_into(&target);
+        assert!(source.inspect_queue().is_empty());
+        assert_eq!(source.stats().bytes_currently_queued, 0);
+        assert_eq!(target.inspect_queue().len(), 2);
+
+        target.advance(Duration::from_millis(69));
+        assert!(recv_all(&target).is_empty());
+        target.advance(Duration::from_millis(1));
+        assert_eq!(recv_all(&target)[0].0, vec![1]);
+        target.advance(Duration::from_millis(20));
+        assert_eq!(recv_all(&target)[0].0, vec![2]);
+        assert_eq!(target.stats().packets_delivered, 2);
+    }
+
+    #[test]
+    fn counts_deliveries_over_the_sla() {
+        let sla = |latency| {
+            ConditionerConfig::builder()
+                .latency(Duration::from_millis(latency))
+                .sla_max_latency(Duration::from_millis(40))
+                .build()
+                .unwrap()
+        };
+        let over = conditioner(sla(50));
+        for i in 0..3 {
+            over.inject(peer(1), vec![i]);
+        }
+        over.advance(Duration::from_millis(50));
+        assert_eq!(recv_all(&over).len(), 3);
+        assert_eq!(over.stats().sla_breaches, 3);
+
+        let within = conditioner(sla(30));
+        within.inject(peer(1), vec![0]);
+        within.advance(Duration::from_millis(30));
+        assert_eq!(recv_all(&within).len(), 1);
+        assert_eq!(within.stats().sla_breaches, 0);
+    }
+
+    #[test]
+    fn jitter_bias_picks_the_side_of_the_offset() {
+        let mut random = StdRng::seed_from_u64(0);
+        for (bias, range) in [(1.0, 0.0..=1.0), (-1.0, -1.0..=0.0)] {
+            let config = ConditionerConfig::builder()
+                .jitter(Duration::from_millis(10))
+                .jitter_bias(bias)
+                .build()
+                .unwrap();
+            for _ in 0..1000 {
+                assert!(range.contains(&jitter_offset(&config, 0.0, &mut random)));
+            }
+        }
+    }
+
+    #[test]
+    fn positive_jitter_bias_never_undercuts_latency() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(20))
+            .jitter(Duration::from_millis(10))
+            .jitter_bias(1.0)
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        for i in 0..100 {
+            conditioner.inject(peer(1), vec![i]);
+        }
+        conditioner.advance(Duration::from_millis(30));
+        let delivered = recv_all(&conditioner);
+        assert_eq!(delivered.len(), 100);
+        assert!(delivered
+            .iter()
+            .all(|(_, annotation)| annotation.delay >= Duration::from_millis(20)));
+    }
+
+    #[test]
+    fn bandwidth_serializes_packets() {
+        let config = ConditionerConfig::builder()
+            .bandwidth(1000)
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        for i in 0..3 {
+            conditioner.inject(peer(1), vec![i; 100]);
+        }
+        for i in 0..3 {
+            conditioner.advance(Duration::from_millis(99));
+            assert!(recv_all(&conditioner).is_empty());
+            conditioner.advance(Duration::from_millis(1));
+            assert_eq!(recv_all(&conditioner)[0].0[0], i);
+        }
+    }
+
+    #[test]
+    fn duplicates_packets_after_a_drop() {
+        let config = ConditionerConfig::builder()
+            .packet_loss_ratio(0.5)
+            .dup_ack_on_loss(2)
+            .build()
+            .unwrap();
+        let random = crate::random::tests::LossRolls {
+            loss: vec![0.9, 0.1, 0.9, 0.9, 0.9],
+            drawn: 0,
+        };
+        let conditioner = Conditioner::with_random(config, MockSocket::default(), random);
+        for i in 0..5 {
+            conditioner.inject(peer(1), vec![i]);
+        }
+        let delivered: Vec<_> = recv_all(&conditioner)
+            .into_iter()
+            .map(|(data, _)| data[0])
+            .collect();
+        assert_eq!(delivered, [0, 2, 2, 3, 3, 4]);
+    }
+
+    #[test]
+    fn rescheduling_through_the_queue_handle() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        conditioner.inject(peer(1), vec![1]);
+
+        let handle = conditioner.queue_handle();
+        {
+            let mut queue = handle.lock().unwrap();
+            let entry = queue.pop_next_entry().unwrap();
+            let sooner = conditioner.clock().now() + Duration::from_millis(10);
+            queue.add_item(sooner, entry.item);
+        }
+        conditioner.advance(Duration::from_millis(10));
+        assert_eq!(recv_all(&conditioner).len(), 1);
+    }
+
+    #[test]
+    fn packets_added_through_the_queue_handle_roll_egress_loss() {
+        let config = ConditionerConfig::builder()
+            .packet_loss_ratio(1.0)
+            .stage_order(StageOrder::LatencyThenLoss)
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        let packet = RecvFrom {
+            addr: peer(1),
+            data: vec![1],
+        };
+        conditioner.queue_handle().lock().unwrap().add_item(
+            conditioner.clock().now(),
+            QueuedPacket::new(packet, DeliveryAnnotation::default()),
+        );
+        assert!(recv_all(&conditioner).is_empty());
+        assert_eq!(conditioner.stats().random_loss_drops, 1);
+    }
+
+    #[test]
+    fn expires_packets_over_max_queue_age() {
+        let config = ConditionerConfig::builder()
+            .max_queue_age(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        conditioner.set_delay_fn(|packet| match packet.data[0] {
+            0 => Duration::from_millis(200),
+            _ => Duration::from_millis(10),
+        });
+        conditioner.inject(peer(1), vec![0]);
+        conditioner.inject(peer(1), vec![1]);
+        conditioner.advance(Duration::from_millis(10));
+        assert_eq!(recv_all(&conditioner)[0].0, vec![1]);
+
+        conditioner.advance(Duration::from_millis(190));
+        assert!(recv_all(&conditioner).is_empty());
+        let stats = conditioner.stats();
+        assert_eq!(stats.packets_expired, 1);
+        assert_eq!(stats.packets_delivered, 1);
+    }
+
+    #[test]
+    fn sequence_follows_arrival_order() {
+        let conditioner = conditioner(ConditionerConfig::default());
+        conditioner.set_delay_fn(|packet| Duration::from_millis(30 - 10 * packet.data[0] as u64));
+        for i in 0..3 {
+            conditioner.inject(peer(1), vec![i]);
+        }
+        conditioner.advance(Duration::from_millis(30));
+        let delivered: Vec<_> = recv_all(&conditioner)
+            .into_iter()
+            .map(|(data, annotation)| (data[0], annotation.sequence))
+            .collect();
+        assert_eq!(delivered, [(2, 2), (1, 1), (0, 0)]);
+    }
+
+    #[test]
+    fn disabled_loss_keeps_other_effects() {
+        let builder = ConditionerConfig::builder()
+            .latency(Duration::from_millis(10))
+            .packet_loss_ratio(1.0)
+            .duplicate_chance(1.0);
+        let config = builder.clone().enable_loss(false).build().unwrap();
+        assert_eq!(config.packet_loss(), 1.0);
+        let lossless = conditioner(config);
+        for i in 0..10 {
+            lossless.inject(peer(1), vec![i]);
+        }
+        lossless.advance(Duration::from_millis(9));
+        assert!(recv_all(&lossless).is_empty());
+        lossless.advance(Duration::from_millis(1));
+        assert_eq!(recv_all(&lossless).len(), 20);
+
+        let config = builder.enable_latency(false).build().unwrap();
+        let lossy = conditioner(config);
+        lossy.inject(peer(1), vec![0]);
+        assert!(recv_all(&lossy).is_empty());
+        assert_eq!(lossy.stats().random_loss_drops, 1);
+    }
+
+    #[test]
+    fn reads_and_writes_a_datagram_per_call() {
+        use std::io::{Read, Write};
+
+        let mut conditioner =
+            Conditioner::bind(ConditionerConfig::default(), "127.0.0.1:0").unwrap();
+        let other = UdpSocket::bind("127.0.0.1:0").unwrap();
+        conditioner.connect(other.local_addr().unwrap()).unwrap();
+        other.connect(conditioner.local_addr().unwrap()).unwrap();
+
+        conditioner.write_all(b"ping").unwrap();
+        let mut buf = [0; 16];
+        assert_eq!(other.recv(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"ping");
+
+        other.send(b"pong").unwrap();
+        other.send(b"").unwrap();
+        conditioner.set_nonblocking(true).unwrap();
+        let mut read = |buf: &mut [u8]| loop {
+            match conditioner.read(buf) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => std::thread::yield_now(),
+                result => return result.unwrap(),
+            }
+        };
+        let mut small = [0; 2];
+        assert_eq!(read(&mut small), 2);
+        assert_eq!(&small, b"po");
+        assert_eq!(read(&mut buf), 0);
+    }
+
+    #[test]
+    fn max_total_delay_clamps_absurd_delay_fn() {
+        let config = ConditionerConfig::builder()
+            .max_total_delay(Duration::from_secs(1))
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        conditioner.set_delay_fn(|_| Duration::MAX);
+        conditioner.inject(peer(1), vec![1]);
+        conditioner.inject(peer(1), vec![2]);
+
+        assert_eq!(conditioner.pending().1, Some(Duration::from_secs(1)));
+        conditioner.advance(Duration::from_secs(1));
+        let delivered = recv_all(&conditioner);
+        assert_eq!(delivered.len(), 2);
+        for (_, annotation) in delivered {
+            assert_eq!(annotation.delay, Duration::from_secs(1));
+        }
+        assert_eq!(conditioner.stats().delays_clamped, 2);
+    }
+
+    #[test]
+    fn max_total_delay_clamps_absurd_extra_latency() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(10))
+            .first_packet_extra_latency(Duration::MAX)
+            .bandwidth(1)
+            .max_total_delay(Duration::from_secs(1))
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        conditioner.inject(peer(1), vec![0; 100]);
+
+        assert_eq!(conditioner.pending().1, Some(Duration::from_secs(1)));
+        assert_eq!(conditioner.stats().delays_clamped, 1);
+    }
+
+    #[test]
+    fn absurd_delay_fn_without_cap_stays_queued() {
+        let conditioner = conditioner(ConditionerConfig::default());
+        conditioner.set_delay_fn(|_| Duration::MAX);
+        conditioner.inject(peer(1), vec![1]);
+
+        conditioner.advance(Duration::from_secs(3600));
+        assert!(recv_all(&conditioner).is_empty());
+        assert_eq!(conditioner.pending().0, 1);
+        assert_eq!(conditioner.stats().delays_clamped, 0);
+    }
+
+    #[test]
+    fn group_packets_get_the_multicast_config() {
+        let lossy = ConditionerConfig::builder()
+            .packet_loss_ratio(1.0)
+            .build()
+            .unwrap();
+        let config = ConditionerConfig::builder()
+            .multicast(lossy)
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        let destinations = [
+            ([239, 1, 2, 3], false),
+            ([255, 255, 255, 255], false),
+            ([192, 168, 1, 255], true),
+            ([127, 0, 0, 1], true),
+        ];
+        for (i, (destination, _)) in destinations.iter().enumerate() {
+            let info = PacketInfo {
+                destination: Some(IpAddr::from(*destination)),
+                ..PacketInfo::default()
+            };
+            conditioner.inject_with_info(peer(1), info, vec![i as u8]);
+        }
+        conditioner.inject(peer(1), vec![4]);
+        let delivered: Vec<_> = recv_all(&conditioner)
+            .into_iter()
+            .map(|(data, _)| data[0])
+            .collect();
+        assert_eq!(delivered, [2, 3, 4]);
+        assert_eq!(conditioner.stats().random_loss_drops, 2);
+    }
+
+    #[test]
+    fn detects_group_addresses() {
+        let mapped = std::net::Ipv4Addr::new(224, 0, 0, 1).to_ipv6_mapped();
+        assert!(is_group_address(IpAddr::V6(mapped)));
+        assert!(is_group_address("ff02::1".parse().unwrap()));
+        assert!(!is_group_address("::1".parse().unwrap()));
+        assert!(!is_group_address(IpAddr::from([10, 0, 0, 255])));
+    }
+
+    #[test]
+    fn reorder_depth_is_bounded_by_lookahead() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(10))
+            .reorder(ReorderMode::Random { chance: 0.3 })
+            .reorder_max_lookahead(3)
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        for n in 0..1000u16 {
+            conditioner.inject(peer(1), n.to_be_bytes().to_vec());
+            conditioner.advance(Duration::from_millis(1));
+        }
+        conditioner.advance(Duration::from_secs(1));
+        let delivered: Vec<_> = recv_all(&conditioner)
+            .into_iter()
+            .map(|(data, _)| u16::from_be_bytes([data[0], data[1]]))
+            .collect();
+        assert!(delivered.len() >= 999);
+        let mut deepest = 0;
+        for (position, n) in delivered.iter().enumerate() {
+            let overtaken_by = delivered[..position].iter().filter(|m| *m > n).count();
+            deepest = deepest.max(overtaken_by);
+        }
+        assert_eq!(deepest, 3);
+    }
+
+    #[test]
+    fn annotates_the_gap_since_the_previous_delivery() {
+        let config = ConditionerConfig::builder()
+            .bandwidth(1000)
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        for i in 0..5 {
+            conditioner.inject(peer(1), vec![i; 100]);
+        }
+        let mut gaps = Vec::new();
+        for _ in 0..50 {
+            conditioner.advance(Duration::from_millis(10));
+            for (_, annotation) in recv_all(&conditioner) {
+                gaps.push(annotation.since_previous_delivery);
+            }
+        }
+        assert_eq!(gaps.len(), 5);
+        assert_eq!(gaps[0], None);
+        assert!(gaps[1..]
+            .iter()
+            .all(|gap| *gap >= Some(Duration::from_millis(100))));
+    }
+
+    #[test]
+    fn corruption_stays_in_range() {
+        let config = ConditionerConfig::builder()
+            .corruption_chance(1.0)
+            .corruption_range(8..)
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        for _ in 0..200 {
+            conditioner.inject(peer(1), vec![0; 16]);
+        }
+        conditioner.inject(peer(1), vec![0; 8]);
+        let delivered = recv_all(&conditioner);
+        assert_eq!(delivered.len(), 201);
+        for (data, annotation) in &delivered[..200] {
+            assert!(annotation.is_corrupted);
+            assert_eq!(data[..8], [0; 8]);
+            assert_eq!(data[8..].iter().map(|b| b.count_ones()).sum::<u32>(), 1);
+        }
+        let (short, annotation) = &delivered[200];
+        assert!(!annotation.is_corrupted);
+        assert_eq!(short, &[0; 8]);
+    }
+
+    #[test]
+    fn reports_truncated_deliveries() {
+        let conditioner = conditioner(ConditionerConfig::default());
+        conditioner.inject(peer(1), (0..100).collect());
+        conditioner.inject(peer(1), vec![1; 10]);
+        conditioner.inject(peer(1), (0..100).collect());
+
+        let mut buf = [0; 10];
+        let (len, addr, truncated) = conditioner.recv_from_trunc(&mut buf).unwrap();
+        assert_eq!((len, addr, truncated), (10, peer(1), true));
+        assert_eq!(buf, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        assert!(!conditioner.recv_from_trunc(&mut buf).unwrap().2);
+
+        let (len, _, metadata) = conditioner.recv_from_with_metadata(&mut buf).unwrap();
+        assert_eq!((len, metadata.truncated_from), (10, Some(100)));
+    }
+
+    #[test]
+    fn tap_mirrors_delivered_packets() {
+        let config = ConditionerConfig::builder()
+            .packet_loss_ratio(1.0)
+            .build()
+            .unwrap();
+        let mut conditioner = conditioner(ConditionerConfig::default());
+        conditioner.set_tap(peer(9));
+        conditioner.inject(peer(1), vec![1]);
+        conditioner.inject(peer(2), vec![2]);
+        assert_eq!(recv_all(&conditioner).len(), 2);
+        {
+            let scope = conditioner.with_config_scope(config);
+            scope.inject(peer(1), vec![3]);
+            assert!(recv_all(&scope).is_empty());
+        }
+        conditioner.clear_tap();
+        conditioner.inject(peer(1), vec![4]);
+        assert_eq!(recv_all(&conditioner).len(), 1);
+
+        let sent = lock(&conditioner.socket.sent).clone();
+        assert_eq!(sent, [(vec![1], Some(peer(9))), (vec![2], Some(peer(9)))]);
+    }
+
+    #[test]
+    fn counts_distinct_peers() {
+        let conditioner = conditioner(ConditionerConfig::default());
+        for port in [1, 2, 1, 3, 2] {
+            conditioner.socket.push(peer(port), vec![0]);
+        }
+        assert_eq!(recv_all(&conditioner).len(), 5);
+        assert_eq!(conditioner.peer_count(), 3);
+        conditioner.clear_peers();
+        assert_eq!(conditioner.peer_count(), 0);
+    }
+
+    #[test]
+    fn bimodal_latency_clusters_around_both_modes() {
+        let config = ConditionerConfig::builder()
+            .latency_distribution(LatencyDistribution::Mixture(vec![
+                (1.0, Duration::from_millis(20), Duration::from_millis(5)),
+                (1.0, Duration::from_millis(200), Duration::from_millis(5)),
+            ]))
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        for i in 0..1000 {
+            conditioner.inject(peer(1), vec![i as u8]);
+        }
+        conditioner.advance(Duration::from_secs(1));
+        let delays: Vec<_> = recv_all(&conditioner)
+            .into_iter()
+            .map(|(_, annotation)| annotation.delay.as_millis())
+            .collect();
+        assert_eq!(delays.len(), 1000);
+        let fast = delays.iter().filter(|ms| (15..=25).contains(*ms)).count();
+        let slow = delays.iter().filter(|ms| (195..=205).contains(*ms)).count();
+        assert_eq!(fast + slow, 1000);
+        assert!((400..600).contains(&fast), "{}", fast);
+    }
+
+    #[test]
+    fn summary_lines_carry_the_label() {
+        let conditioner = conditioner(ConditionerConfig::default());
+        conditioner.inject(peer(1), vec![0]);
+        let summary = conditioner.stats().summary();
+        assert_eq!(conditioner.summary(), summary);
+
+        conditioner.set_label("client");
+        let labeled = conditioner.summary();
+        assert_eq!(labeled.lines().count(), summary.lines().count());
+        for (labeled, line) in labeled.lines().zip(summary.lines()) {
+            assert_eq!(labeled, format!("[client] {}", line));
+        }
+        assert!(labeled.ends_with('\n'));
+    }
+
+    #[test]
+    fn injected_error_surfaces_once() {
+        let conditioner = conditioner(ConditionerConfig::default());
+        let at = conditioner.clock().now() + Duration::from_millis(10);
+        conditioner.inject_error(io::ErrorKind::ConnectionReset, at);
+        conditioner.inject(peer(1), vec![1]);
+        let mut buf = [0; 16];
+        assert_eq!(conditioner.recv_from(&mut buf).unwrap(), (1, peer(1)));
+
+        conditioner.inject(peer(1), vec![2]);
+        conditioner.advance(Duration::from_millis(10));
+        let err = conditioner.recv_from(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!(conditioner.recv_from(&mut buf).unwrap(), (1, peer(1)));
+        let err = conditioner.recv_from(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn delay_is_measured_from_the_read() {
+        let config = ConditionerConfig::default();
+        let mut conditioner = Conditioner::with_seed(config, MockSocket::default(), 0);
+        let clock = MockClock::new();
+        conditioner.set_clock(Clock::Mock(clock.clone()));
+        let start = clock.now();
+        // Conditioning takes 15ms of mock time after the packet was read.
+        conditioner.set_delay_fn(move |_| {
+            clock.advance(Duration::from_millis(15));
+            Duration::from_millis(20)
+        });
+
+        conditioner.socket.push(peer(1), vec![1]);
+        assert!(recv_all(&conditioner).is_empty());
+        conditioner.advance(Duration::from_millis(5));
+        assert_eq!(conditioner.clock().now() - start, Duration::from_millis(20));
+        let delivered = recv_all(&conditioner);
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0].1.delay, Duration::from_millis(20));
+    }
 }
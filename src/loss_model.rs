@@ -0,0 +1,112 @@
+//! Bursty loss, where a drop makes the next packet more likely to be dropped.
+
+/// Two-state Gilbert-Elliott loss: every packet is lost while the link is in
+/// its bad state and delivered while it is in its good state.
+///
+/// Before each packet the link moves from good to bad with probability `p`
+/// and from bad to good with probability `r`, so bursts last `1 / r` packets
+/// on average and the overall loss is `p / (p + r)`. `p = loss` and
+/// `r = 1 - loss` is plain random loss.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LossModel {
+    /// Chance of a burst starting, in the `0.0 ..= 1.0` range.
+    pub p: f32,
+    /// Chance of a burst ending, in the `0.0 ..= 1.0` range.
+    pub r: f32,
+}
+
+impl LossModel {
+    /// Fits `p` and `r` to a captured trace, where `true` is a lost packet,
+    /// from how often a delivered packet was followed by a lost one and the
+    /// other way around.
+    ///
+    /// A state the trace never leaves falls back to the trace's loss ratio,
+    /// e.g. a trace without losses gives `p = 0`, one without deliveries
+    /// `r = 0`.
+    pub fn from_trace(trace: &[bool]) -> Self {
+        let lost = trace.iter().filter(|&&lost| lost).count();
+        let loss = if trace.is_empty() {
+            0.0
+        } else {
+            lost as f32 / trace.len() as f32
+        };
+
+        let (mut from_good, mut good_to_bad) = (0u64, 0u64);
+        let (mut from_bad, mut bad_to_good) = (0u64, 0u64);
+        for pair in trace.windows(2) {
+            match (pair[0], pair[1]) {
+                (false, next) => {
+                    from_good += 1;
+                    good_to_bad += next as u64;
+                }
+                (true, next) => {
+                    from_bad += 1;
+                    bad_to_good += !next as u64;
+                }
+            }
+        }
+
+        let ratio = |count: u64, total: u64, fallback: f32| match total {
+            0 => fallback,
+            _ => count as f32 / total as f32,
+        };
+        LossModel {
+            p: ratio(good_to_bad, from_good, loss),
+            r: ratio(bad_to_good, from_bad, 1.0 - loss),
+        }
+    }
+
+    /// Long run fraction of packets lost.
+    pub fn loss_ratio(&self) -> f32 {
+        if self.p + self.r == 0.0 {
+            return 0.0;
+        }
+        self.p / (self.p + self.r)
+    }
+
+    /// Moves the link to its state for the next packet given the roll `n`,
+    /// returning whether the packet is lost.
+    pub(crate) fn step(&self, bad: &mut bool, n: f32) -> bool {
+        let chance = if *bad { self.r } else { self.p };
+        if n < chance {
+            *bad = !*bad;
+        }
+        *bad
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LossModel;
+
+    #[test]
+    fn fits_a_bursty_trace() {
+        let cycle = [vec![false; 18], vec![true; 2]].concat();
+        let trace = cycle.repeat(50);
+        let model = LossModel::from_trace(&trace);
+        assert!((0.05..0.06).contains(&model.p), "{:?}", model);
+        assert!((0.49..0.51).contains(&model.r), "{:?}", model);
+        assert!((model.loss_ratio() - 0.1).abs() < 0.005);
+    }
+
+    #[test]
+    fn states_never_left_fall_back_to_the_loss_ratio() {
+        let model = LossModel::from_trace(&[false; 10]);
+        assert_eq!(model, LossModel { p: 0.0, r: 1.0 });
+        let model = LossModel::from_trace(&[true; 10]);
+        assert_eq!(model, LossModel { p: 1.0, r: 0.0 });
+        assert_eq!(model.loss_ratio(), 1.0);
+        let model = LossModel::from_trace(&[]);
+        assert_eq!(model, LossModel { p: 0.0, r: 1.0 });
+    }
+
+    #[test]
+    fn step_flips_state_on_a_low_roll() {
+        let model = LossModel { p: 0.25, r: 0.5 };
+        let mut bad = false;
+        assert!(!model.step(&mut bad, 0.3));
+        assert!(model.step(&mut bad, 0.2));
+        assert!(model.step(&mut bad, 0.6));
+        assert!(!model.step(&mut bad, 0.4));
+    }
+}
@@ -0,0 +1,181 @@
+//! Parsing of Linux `tc netem` style parameters.
+//!
+//! Supports the subset that maps onto the conditioner:
+//!
+//! - `delay TIME [JITTER [CORRELATION]]`
+//! - `loss [random] PERCENT`
+//! - `duplicate PERCENT`
+//! - `reorder PERCENT [CORRELATION]`, the correlation is accepted but ignored
+//! - `corrupt PERCENT`
+//!
+//! Times take a `us`/`usec`, `ms`/`msec` or `s`/`sec` suffix, a bare number is
+//! in microseconds like in `tc`. Percentages need a `%` suffix, e.g.
+//! `delay 100ms 20ms 25% loss 0.3% duplicate 1% reorder 25% 50%`.
+
+use std::{fmt, time::Duration};
+
+use crate::{ConditionerConfig, ReorderMode};
+
+/// Error returned by [`ConditionerConfig::from_netem_str`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetemParseError(String);
+
+impl fmt::Display for NetemParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid netem parameters: {}", self.0)
+    }
+}
+
+impl std::error::Error for NetemParseError {}
+
+impl ConditionerConfig {
+    /// Builds a config from `tc netem` style parameters, see the
+    /// [module docs](crate::netem) for the supported subset.
+    pub fn from_netem_str(s: &str) -> Result<Self, NetemParseError> {
+        let mut builder = ConditionerConfig::builder();
+        let mut tokens = s.split_whitespace().peekable();
+        while let Some(keyword) = tokens.next() {
+            match keyword {
+                "delay" => {
+                    builder = builder.latency(parse_time(tokens.next(), "delay")?);
+                    if let Some(jitter) = tokens.next_if(|token| is_time(token)) {
+                        builder = builder.jitter(parse_time(Some(jitter), "delay jitter")?);
+                        if let Some(correlation) = tokens.next_if(|token| is_percent(token)) {
+                            builder = builder.jitter_correlation(parse_percent(
+                                Some(correlation),
+                                "delay correlation",
+                            )?);
+                        }
+                    }
+                }
+                "loss" => {
+                    tokens.next_if_eq(&"random");
+                    builder = builder.packet_loss_ratio(parse_percent(tokens.next(), "loss")?);
+                }
+                "duplicate" => {
+                    builder = builder.duplicate_chance(parse_percent(tokens.next(), "duplicate")?);
+                }
+                "reorder" => {
+                    let chance = parse_percent(tokens.next(), "reorder")?;
+                    tokens.next_if(|token| is_percent(token));
+                    builder = builder.reorder(ReorderMode::Random { chance });
+                }
+                "corrupt" => {
+                    builder = builder.corruption_chance(parse_percent(tokens.next(), "corrupt")?);
+                }
+                other => {
+                    return Err(NetemParseError(format!("unsupported keyword `{}`", other)));
+                }
+            }
+        }
+        builder
+            .build()
+            .map_err(|err| NetemParseError(err.to_string()))
+    }
+}
+
+fn is_time(token: &str) -> bool {
+    token.starts_with(|c: char| c.is_ascii_digit() || c == '.') && !token.ends_with('%')
+}
+
+fn is_percent(token: &str) -> bool {
+    token.ends_with('%')
+}
+
+fn parse_time(token: Option<&str>, name: &str) -> Result<Duration, NetemParseError> {
+    let token = token.ok_or_else(|| NetemParseError(format!("missing {} time", name)))?;
+    let error = || NetemParseError(format!("invalid {} time `{}`", name, token));
+    let split = token
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(token.len());
+    let (value, unit) = token.split_at(split);
+    let value: f64 = value.parse().map_err(|_| error())?;
+    let seconds = match unit {
+        "" | "us" | "usec" => value / 1_000_000.0,
+        "ms" | "msec" => value / 1_000.0,
+        "s" | "sec" | "secs" => value,
+        _ => return Err(error()),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|_| error())
+}
+
+fn parse_percent(token: Option<&str>, name: &str) -> Result<f32, NetemParseError> {
+    let token = token.ok_or_else(|| NetemParseError(format!("missing {} percentage", name)))?;
+    token
+        .strip_suffix('%')
+        .and_then(|value| value.parse::<f32>().ok())
+        .map(|percent| percent / 100.0)
+        .ok_or_else(|| NetemParseError(format!("invalid {} percentage `{}`", name, token)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx(actual: f32, expected: f32) -> bool {
+        (actual - expected).abs() < 1e-6
+    }
+
+    #[test]
+    fn parses_every_keyword() {
+        let config = ConditionerConfig::from_netem_str(
+            "delay 100ms 20ms 25% loss random 0.3% duplicate 1% reorder 25% 50% corrupt 0.1%",
+        )
+        .unwrap();
+        assert_eq!(config.latency(), Duration::from_millis(100));
+        assert_eq!(config.jitter(), Duration::from_millis(20));
+        assert!(approx(config.jitter_correlation(), 0.25));
+        assert!(approx(config.packet_loss(), 0.003));
+        assert!(approx(config.duplicate_chance(), 0.01));
+        assert!(matches!(
+            config.reorder(),
+            Some(ReorderMode::Random { chance }) if approx(chance, 0.25)
+        ));
+        assert!(approx(config.corruption_chance(), 0.001));
+    }
+
+    #[test]
+    fn parses_time_units() {
+        for (delay, expected) in [
+            ("delay 250", Duration::from_micros(250)),
+            ("delay 250us", Duration::from_micros(250)),
+            ("delay 1.5msec", Duration::from_micros(1500)),
+            ("delay 2s", Duration::from_secs(2)),
+        ] {
+            let config = ConditionerConfig::from_netem_str(delay).unwrap();
+            assert_eq!(config.latency(), expected, "{}", delay);
+        }
+        let config = ConditionerConfig::from_netem_str("delay 10ms loss 1%").unwrap();
+        assert_eq!(config.jitter(), Duration::ZERO);
+    }
+
+    #[test]
+    fn rejects_unsupported_input() {
+        let error = |s| {
+            ConditionerConfig::from_netem_str(s)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("rate 1mbit"),
+            "invalid netem parameters: unsupported keyword `rate`"
+        );
+        assert_eq!(
+            error("delay 10 parsecs"),
+            "invalid netem parameters: unsupported keyword `parsecs`"
+        );
+        assert_eq!(
+            error("delay 10lightyears"),
+            "invalid netem parameters: invalid delay time `10lightyears`"
+        );
+        assert_eq!(
+            error("loss 5"),
+            "invalid netem parameters: invalid loss percentage `5`"
+        );
+        assert_eq!(
+            error("duplicate"),
+            "invalid netem parameters: missing duplicate percentage"
+        );
+        assert!(error("loss 150%").contains("outside of 0.0..=1.0"));
+    }
+}
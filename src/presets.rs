@@ -0,0 +1,223 @@
+//! Baseline numbers for common link types.
+//!
+//! Each preset is exposed both as a set of constants and as a constructor on
+//! [`ConditionerConfig`], so custom configs can start from the same baseline.
+
+use std::time::Duration;
+
+use crate::{config::ConfigError, ConditionerConfig, ReorderMode};
+
+/// One-way latency of a typical home wifi link.
+pub const WIFI_LATENCY: Duration = Duration::from_millis(5);
+/// Jitter of a typical home wifi link.
+pub const WIFI_JITTER: Duration = Duration::from_millis(2);
+/// Packet loss ratio of a typical home wifi link.
+pub const WIFI_LOSS: f32 = 0.005;
+
+/// One-way latency of a mobile (4G) link.
+pub const MOBILE_LATENCY: Duration = Duration::from_millis(50);
+/// Jitter of a mobile (4G) link.
+pub const MOBILE_JITTER: Duration = Duration::from_millis(20);
+/// Packet loss ratio of a mobile (4G) link.
+pub const MOBILE_LOSS: f32 = 0.02;
+
+/// One-way latency of a geostationary satellite link.
+pub const SATELLITE_LATENCY: Duration = Duration::from_millis(300);
+/// Jitter of a geostationary satellite link.
+pub const SATELLITE_JITTER: Duration = Duration::from_millis(30);
+/// Packet loss ratio of a geostationary satellite link.
+pub const SATELLITE_LOSS: f32 = 0.01;
+
+/// One-way latency of the chaos preset.
+pub const CHAOS_LATENCY: Duration = Duration::from_millis(100);
+/// Jitter of the chaos preset.
+pub const CHAOS_JITTER: Duration = Duration::from_millis(50);
+/// Packet loss ratio of the chaos preset.
+pub const CHAOS_LOSS: f32 = 0.05;
+/// Duplicate chance of the chaos preset.
+pub const CHAOS_DUPLICATE: f32 = 0.02;
+/// Random reorder chance of the chaos preset.
+pub const CHAOS_REORDER: f32 = 0.05;
+/// Corruption chance of the chaos preset.
+pub const CHAOS_CORRUPTION: f32 = 0.01;
+
+/// One-way latency light picks up per kilometre of fiber, about two thirds of
+/// its speed in vacuum.
+pub const FIBER_LATENCY_PER_KM: Duration = Duration::from_micros(5);
+/// Fixed one-way latency [`ConditionerConfig::from_distance_km`] adds on top
+/// of the fiber, for routing, queueing and the last mile.
+pub const DISTANCE_OVERHEAD: Duration = Duration::from_millis(5);
+/// Jitter of [`ConditionerConfig::from_distance_km`], as a ratio of the
+/// resulting latency.
+pub const DISTANCE_JITTER_RATIO: f32 = 0.1;
+
+impl ConditionerConfig {
+    /// Home wifi, see [`WIFI_LATENCY`], [`WIFI_JITTER`] and [`WIFI_LOSS`].
+    pub fn wifi() -> Self {
+        Self {
+            latency: WIFI_LATENCY,
+            jitter: WIFI_JITTER,
+            packet_loss: WIFI_LOSS,
+            ..Self::default()
+        }
+    }
+
+    /// Mobile (4G), see [`MOBILE_LATENCY`], [`MOBILE_JITTER`] and [`MOBILE_LOSS`].
+    pub fn mobile() -> Self {
+        Self {
+            latency: MOBILE_LATENCY,
+            jitter: MOBILE_JITTER,
+            packet_loss: MOBILE_LOSS,
+            ..Self::default()
+        }
+    }
+
+    /// Geostationary satellite, see [`SATELLITE_LATENCY`], [`SATELLITE_JITTER`]
+    /// and [`SATELLITE_LOSS`].
+    pub fn satellite() -> Self {
+        Self {
+            latency: SATELLITE_LATENCY,
+            jitter: SATELLITE_JITTER,
+            packet_loss: SATELLITE_LOSS,
+            ..Self::default()
+        }
+    }
+
+    /// Link spanning `distance` km of fiber, e.g. `10_000.0` from Tokyo to
+    /// Virginia, with [`DISTANCE_OVERHEAD`] on top.
+    ///
+    /// Latency is `distance * FIBER_LATENCY_PER_KM + DISTANCE_OVERHEAD`,
+    /// saturating at [`Duration::MAX`], and jitter [`DISTANCE_JITTER_RATIO`]
+    /// of that. No loss is added. Negative distances count as zero, NaN and
+    /// infinite ones are refused.
+    pub fn from_distance_km(distance: f64) -> Result<Self, ConfigError> {
+        Self::from_distance_km_with_overhead(distance, DISTANCE_OVERHEAD)
+    }
+
+    /// Like [`ConditionerConfig::from_distance_km`] with a custom fixed
+    /// `overhead` instead of [`DISTANCE_OVERHEAD`].
+    pub fn from_distance_km_with_overhead(
+        distance: f64,
+        overhead: Duration,
+    ) -> Result<Self, ConfigError> {
+        if !distance.is_finite() {
+            return Err(ConfigError::Distance(distance));
+        }
+        let fiber = FIBER_LATENCY_PER_KM.as_secs_f64() * distance.max(0.0);
+        let fiber = Duration::try_from_secs_f64(fiber).unwrap_or(Duration::MAX);
+        let latency = fiber.saturating_add(overhead);
+        Ok(Self {
+            latency,
+            jitter: latency.mul_f32(DISTANCE_JITTER_RATIO),
+            ..Self::default()
+        })
+    }
+
+    /// Every effect at once: latency, jitter, loss, duplication, reordering
+    /// and corruption, see the `CHAOS_*` constants.
+    ///
+    /// Use [`Conditioner::chaos_with_seed`](crate::Conditioner::chaos_with_seed)
+    /// to replay the same run.
+    pub fn chaos() -> Self {
+        Self {
+            latency: CHAOS_LATENCY,
+            jitter: CHAOS_JITTER,
+            packet_loss: CHAOS_LOSS,
+            duplicate_chance: CHAOS_DUPLICATE,
+            reorder: Some(ReorderMode::Random {
+                chance: CHAOS_REORDER,
+            }),
+            corruption_chance: CHAOS_CORRUPTION,
+            ..Self::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn satellite_matches_constants() {
+        let config = ConditionerConfig::builder()
+            .latency(SATELLITE_LATENCY)
+            .jitter(SATELLITE_JITTER)
+            .packet_loss_ratio(SATELLITE_LOSS)
+            .build()
+            .unwrap();
+        assert_eq!(ConditionerConfig::satellite(), config);
+    }
+
+    #[test]
+    fn chaos_enables_every_effect() {
+        let config = ConditionerConfig::chaos();
+        assert!(config.latency() > Duration::ZERO);
+        assert!(config.jitter() > Duration::ZERO);
+        assert!(config.packet_loss() > 0.0);
+        assert!(config.duplicate_chance() > 0.0);
+        assert!(config.corruption_chance() > 0.0);
+        assert!(matches!(
+            config.reorder(),
+            Some(ReorderMode::Random { chance }) if chance > 0.0
+        ));
+    }
+
+    #[test]
+    fn chaos_with_seed_is_reproducible() {
+        use crate::{
+            clock::{Clock, MockClock},
+            tests::{peer, recv_all, MockSocket},
+            Conditioner,
+        };
+
+        let run = || {
+            let mut conditioner = Conditioner::chaos_with_seed(MockSocket::default(), 139);
+            conditioner.set_clock(Clock::Mock(MockClock::new()));
+            for n in 0..1000 {
+                conditioner.inject(peer(1), (n as u32).to_le_bytes().to_vec());
+            }
+            conditioner.advance(Duration::from_secs(1));
+            recv_all(&conditioner)
+        };
+        let delivered = run();
+        assert_eq!(delivered, run());
+        assert!(delivered.len() < 1000);
+        assert!(delivered
+            .iter()
+            .any(|(_, annotation)| annotation.is_duplicate));
+        assert!(delivered
+            .iter()
+            .any(|(_, annotation)| annotation.is_corrupted));
+    }
+
+    #[test]
+    fn distance_adds_fiber_latency() {
+        let config = ConditionerConfig::from_distance_km(10_000.0).unwrap();
+        assert_eq!(config.latency(), Duration::from_millis(55));
+        assert_eq!(config.jitter(), Duration::from_micros(5500));
+        assert_eq!(config.packet_loss(), 0.0);
+
+        let config =
+            ConditionerConfig::from_distance_km_with_overhead(-1.0, Duration::ZERO).unwrap();
+        assert_eq!(config.latency(), Duration::ZERO);
+    }
+
+    #[test]
+    fn distance_refuses_non_finite() {
+        for distance in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let err = ConditionerConfig::from_distance_km(distance).unwrap_err();
+            assert!(matches!(err, ConfigError::Distance(d) if d.is_nan() || d == distance));
+        }
+    }
+
+    #[test]
+    fn huge_distance_saturates() {
+        for distance in [f64::MAX, 1e300, 1e25] {
+            let config = ConditionerConfig::from_distance_km(distance).unwrap();
+            assert_eq!(config.latency(), Duration::MAX);
+            assert!(config.jitter() > Duration::ZERO);
+        }
+        let config = ConditionerConfig::from_distance_km_with_overhead(1.0, Duration::MAX).unwrap();
+        assert_eq!(config.latency(), Duration::MAX);
+    }
+}
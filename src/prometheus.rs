@@ -0,0 +1,191 @@
+//! Prometheus text exposition of [`ConditionerStats`].
+
+use std::fmt::Write;
+
+use crate::stats::ConditionerStats;
+
+impl ConditionerStats {
+    /// Renders the stats in the Prometheus text exposition format, each
+    /// metric name starting with `prefix`, ready to be served from an HTTP
+    /// handler.
+    pub fn to_prometheus(&self, prefix: &str) -> String {
+        let mut out = String::new();
+        let counters = [
+            (
+                "packets_received_total",
+                "Packets that arrived at the conditioner.",
+                self.packets_received,
+            ),
+            (
+                "packets_filtered_total",
+                "Packets discarded by the source allowlist.",
+                self.packets_filtered,
+            ),
+            (
+                "packets_queued_total",
+                "Packets put in the delivery queue.",
+                self.packets_queued,
+            ),
+            (
+                "packets_delivered_total",
+                "Packets handed back to the caller.",
+                self.packets_delivered,
+            ),
+            (
+                "bytes_delivered_total",
+                "Bytes of packets handed back to the caller.",
+                self.bytes_delivered_total,
+            ),
+            (
+                "packets_corrupted_total",
+                "Packets that had a bit flipped.",
+                self.packets_corrupted,
+            ),
+            (
+                "packets_duplicated_total",
+                "Extra copies of packets queued.",
+                self.packets_duplicated,
+            ),
+            (
+                "packets_reordered_total",
+                "Packets held back so later ones overtake them.",
+                self.packets_reordered,
+            ),
+            (
+                "random_loss_drops_total",
+                "Packets dropped by the packet_loss roll.",
+                self.random_loss_drops,
+            ),
+            (
+                "congestion_drops_total",
+                "Packets dropped by congestion loss.",
+                self.congestion_drops,
+            ),
+            (
+                "link_down_drops_total",
+                "Packets dropped while the link was down.",
+                self.link_down_drops,
+            ),
+            (
+                "packets_expired_total",
+                "Packets dropped for exceeding max_queue_age.",
+                self.packets_expired,
+            ),
+            (
+                "queue_overflow_drops_total",
+                "Packets dropped for arriving at a full queue.",
+                self.queue_overflow_drops,
+            ),
+            (
+                "delays_clamped_total",
+                "Packets whose delay was capped by max_total_delay.",
+                self.delays_clamped,
+            ),
+            (
+                "sla_breaches_total",
+                "Delivered packets that exceeded sla_max_latency.",
+                self.sla_breaches,
+            ),
+            (
+                "would_drop_total",
+                "Packets that would have been dropped in dry-run mode.",
+                self.would_drop,
+            ),
+        ];
+        for (name, help, value) in counters {
+            metric(&mut out, prefix, name, help, "counter");
+            let _ = writeln!(out, "{}_{} {}", prefix, name, value);
+        }
+
+        metric(
+            &mut out,
+            prefix,
+            "would_delay_seconds_total",
+            "Delay that would have been applied in dry-run mode.",
+            "counter",
+        );
+        let _ = writeln!(
+            out,
+            "{}_would_delay_seconds_total {}",
+            prefix,
+            self.would_delay.as_secs_f64()
+        );
+
+        metric(
+            &mut out,
+            prefix,
+            "drop_bursts_total",
+            "Runs of consecutive drops, by run length.",
+            "counter",
+        );
+        for (run_length, count) in &self.drop_bursts {
+            let _ = writeln!(
+                out,
+                "{}_drop_bursts_total{{run_length=\"{}\"}} {}",
+                prefix, run_length, count
+            );
+        }
+
+        metric(
+            &mut out,
+            prefix,
+            "queue_len",
+            "Packets waiting in the delivery queue.",
+            "gauge",
+        );
+        let _ = writeln!(out, "{}_queue_len {}", prefix, self.queue_len);
+
+        metric(
+            &mut out,
+            prefix,
+            "queue_bytes",
+            "Bytes waiting in the delivery queue.",
+            "gauge",
+        );
+        let _ = writeln!(
+            out,
+            "{}_queue_bytes {}",
+            prefix, self.bytes_currently_queued
+        );
+        out
+    }
+}
+
+fn metric(out: &mut String, prefix: &str, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {}_{} {}", prefix, name, help);
+    let _ = writeln!(out, "# TYPE {}_{} {}", prefix, name, kind);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::stats::ConditionerStats;
+
+    #[test]
+    fn renders_counters_and_gauges() {
+        let stats = ConditionerStats {
+            packets_received: 12,
+            random_loss_drops: 3,
+            bytes_currently_queued: 40,
+            queue_len: 2,
+            drop_bursts: BTreeMap::from([(1, 2), (4, 1)]),
+            ..ConditionerStats::default()
+        };
+        let text = stats.to_prometheus("link");
+        for line in [
+            "# HELP link_packets_received_total Packets that arrived at the conditioner.",
+            "# TYPE link_packets_received_total counter",
+            "link_packets_received_total 12",
+            "link_random_loss_drops_total 3",
+            "link_drop_bursts_total{run_length=\"1\"} 2",
+            "link_drop_bursts_total{run_length=\"4\"} 1",
+            "# TYPE link_queue_len gauge",
+            "link_queue_len 2",
+            "# TYPE link_queue_bytes gauge",
+            "link_queue_bytes 40",
+        ] {
+            assert!(text.lines().any(|rendered| rendered == line), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Pluggable randomness for the conditioner's loss/jitter decisions.
+
+use rand::{rngs::StdRng, Rng};
+
+/// Source of the random numbers used to decide packet fates.
+///
+/// Every packet that reaches the conditioning stages (i.e. wasn't filtered
+/// by the allowlist or swallowed by a link outage) takes the same seven
+/// values, in this order, whether or not the effect they feed is enabled:
+///
+/// 1. loss roll
+/// 2. jitter
+/// 3. duplicate roll
+/// 4. reorder roll
+/// 5. corruption roll
+/// 6. corrupted byte
+/// 7. corrupted bit
+///
+/// followed by the latency mode if a
+/// [`LatencyDistribution`](crate::LatencyDistribution) is configured. So with
+/// a seeded source, toggling one effect or changing its chance
+/// leaves every other effect's decisions unchanged. That includes the stage
+/// order: with [`StageOrder::LatencyThenLoss`](crate::StageOrder::LatencyThenLoss)
+/// the loss roll is still drawn on arrival and only applied when the packet
+/// leaves the queue, to a duplicate as well as its original.
+pub trait RandomSource {
+    /// Returns a value in the `0.0 .. 1.0` range.
+    fn next_f32(&mut self) -> f32;
+
+    /// Returns a value in the `lo .. hi` range.
+    fn next_range(&mut self, lo: f32, hi: f32) -> f32 {
+        lo + (hi - lo) * self.next_f32()
+    }
+}
+
+/// Default source, backed by `rand`'s thread local generator.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultRandom;
+
+impl RandomSource for DefaultRandom {
+    fn next_f32(&mut self) -> f32 {
+        rand::random::<f32>()
+    }
+}
+
+/// Seedable source, e.g. `StdRng::seed_from_u64(1)` for reproducible runs.
+impl RandomSource for StdRng {
+    fn next_f32(&mut self) -> f32 {
+        self.gen::<f32>()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::time::Duration;
+
+    use super::RandomSource;
+    use crate::{
+        tests::{conditioner, peer, recv_all, MockSocket},
+        Conditioner, ConditionerConfig, StageOrder,
+    };
+
+    /// Source handing out `loss` as the loss roll of each packet in turn and
+    /// `0.5` for every other value.
+    pub(crate) struct LossRolls {
+        pub(crate) loss: Vec<f32>,
+        pub(crate) drawn: usize,
+    }
+
+    impl RandomSource for LossRolls {
+        fn next_f32(&mut self) -> f32 {
+            let n = self.drawn;
+            self.drawn += 1;
+            match n % 7 {
+                0 => self.loss[n / 7 % self.loss.len()],
+                _ => 0.5,
+            }
+        }
+    }
+
+    #[test]
+    fn next_range_scales_the_draw() {
+        let mut random = LossRolls {
+            loss: vec![0.25],
+            drawn: 0,
+        };
+        assert_eq!(random.next_range(2.0, 6.0), 3.0);
+        assert_eq!(random.next_range(-1.0, 1.0), 0.0);
+    }
+
+    #[test]
+    fn custom_source_decides_drops() {
+        let config = ConditionerConfig::builder()
+            .packet_loss_ratio(0.5)
+            .build()
+            .unwrap();
+        let random = LossRolls {
+            loss: vec![0.1, 0.9, 0.9, 0.1],
+            drawn: 0,
+        };
+        let conditioner = Conditioner::with_random(config, MockSocket::default(), random);
+        for n in 0..8 {
+            conditioner.inject(peer(1), vec![n]);
+        }
+        let delivered: Vec<_> = recv_all(&conditioner)
+            .into_iter()
+            .map(|(data, _)| data[0])
+            .collect();
+        assert_eq!(delivered, [1, 2, 5, 6]);
+    }
+
+    /// Sequence numbers of the packets out of 100 that aren't dropped.
+    fn kept(corruption_chance: f32) -> Vec<u64> {
+        let config = ConditionerConfig::builder()
+            .packet_loss_ratio(0.3)
+            .jitter(Duration::from_millis(5))
+            .corruption_chance(corruption_chance)
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        for _ in 0..100 {
+            conditioner.inject(peer(1), vec![0; 8]);
+        }
+        conditioner.advance(Duration::from_millis(10));
+        let mut kept: Vec<_> = recv_all(&conditioner)
+            .into_iter()
+            .map(|(_, annotation)| annotation.sequence)
+            .collect();
+        kept.sort_unstable();
+        kept
+    }
+
+    #[test]
+    fn toggling_corruption_leaves_drops_unchanged() {
+        let kept_without = kept(0.0);
+        assert!(kept_without.len() < 100);
+        assert_eq!(kept(0.5), kept_without);
+    }
+
+    /// Sequence, duplicate flag and delay of the packets out of 100 that
+    /// aren't dropped.
+    fn delivered(stage_order: StageOrder) -> Vec<(u64, bool, Duration)> {
+        let config = ConditionerConfig::builder()
+            .packet_loss_ratio(0.3)
+            .latency(Duration::from_millis(10))
+            .jitter(Duration::from_millis(5))
+            .duplicate_chance(0.2)
+            .stage_order(stage_order)
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        for _ in 0..100 {
+            conditioner.inject(peer(1), vec![0; 8]);
+        }
+        conditioner.advance(Duration::from_millis(20));
+        let mut delivered: Vec<_> = recv_all(&conditioner)
+            .into_iter()
+            .map(|(_, annotation)| {
+                let delay = annotation.delay;
+                (annotation.sequence, annotation.is_duplicate, delay)
+            })
+            .collect();
+        delivered.sort_unstable();
+        delivered
+    }
+
+    #[test]
+    fn toggling_stage_order_leaves_fates_unchanged() {
+        let loss_first = delivered(StageOrder::LossThenLatency);
+        assert!(loss_first.len() < 100);
+        assert!(loss_first.iter().any(|&(_, duplicate, _)| duplicate));
+        assert_eq!(delivered(StageOrder::LatencyThenLoss), loss_first);
+    }
+}
@@ -0,0 +1,341 @@
+//! A receiver with a limited reassembly buffer, for protocols that split
+//! their messages into several datagrams themselves.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    hash::Hash,
+    io,
+    net::SocketAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    lock,
+    random::{DefaultRandom, RandomSource},
+    wrapper::{conditioner_accessors, recv_packet},
+    Conditioner, RecvFrom, SocketLike,
+};
+
+/// Which fragment set a packet belongs to, returned by the key extractor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FragmentKey<K> {
+    /// Identifies the set, e.g. a message id along with the sender.
+    pub set: K,
+    /// How many fragments make up the whole set.
+    pub count: usize,
+}
+
+/// Wraps a conditioner, holding conditioned fragments back until their set
+/// is complete and discarding sets the receiver couldn't have reassembled.
+///
+/// The wrapper knows nothing about the protocol, so it needs a key extractor
+/// that reads the set and its fragment count out of each packet. Packets the
+/// extractor returns `None` for are delivered as they arrive.
+///
+/// A set is delivered, in arrival order, once `count` of its fragments have
+/// arrived, duplicates included. It is dropped whole if it is still
+/// incomplete `timeout` after its first fragment arrived, or if one of its
+/// fragments arrives while `max_fragments` are already held across all sets,
+/// unless that fragment completes it.
+/// Fragments arriving after their set was dropped start it over.
+///
+/// Timeouts are checked on the conditioner's clock whenever `recv_from` is
+/// called.
+pub struct FragmentReassemblySim<S, K, R = DefaultRandom> {
+    conditioner: Conditioner<S, R>,
+    key: KeyFn<K>,
+    max_fragments: usize,
+    timeout: Duration,
+    state: Mutex<ReassemblyState<K>>,
+}
+
+/// User supplied extractor of the set a packet belongs to.
+type KeyFn<K> = Box<dyn Fn(&RecvFrom) -> Option<FragmentKey<K>> + Send + Sync>;
+
+#[derive(Debug)]
+struct ReassemblyState<K> {
+    pending: HashMap<K, PendingSet>,
+    held: usize,
+    ready: VecDeque<RecvFrom>,
+    dropped_sets: u64,
+}
+
+#[derive(Debug)]
+struct PendingSet {
+    first_arrival: Instant,
+    count: usize,
+    fragments: Vec<RecvFrom>,
+}
+
+impl<K> ReassemblyState<K>
+where
+    K: Hash + Eq,
+{
+    fn expire(&mut self, now: Instant, timeout: Duration) {
+        let (held, dropped_sets) = (&mut self.held, &mut self.dropped_sets);
+        self.pending.retain(|_, set| {
+            let expired = now.saturating_duration_since(set.first_arrival) >= timeout;
+            if expired {
+                *held -= set.fragments.len();
+                *dropped_sets += 1;
+            }
+            !expired
+        });
+    }
+
+    fn add(&mut self, key: FragmentKey<K>, packet: RecvFrom, now: Instant, max_fragments: usize) {
+        let (held, count) = self
+            .pending
+            .get(&key.set)
+            .map_or((0, key.count), |set| (set.fragments.len(), set.count));
+        // Completing a set frees its fragments, so it never overflows.
+        if held + 1 >= count {
+            if let Some(set) = self.pending.remove(&key.set) {
+                self.held -= set.fragments.len();
+                self.ready.extend(set.fragments);
+            }
+            self.ready.push_back(packet);
+            return;
+        }
+
+        if self.held >= max_fragments {
+            if let Some(set) = self.pending.remove(&key.set) {
+                self.held -= set.fragments.len();
+            }
+            self.dropped_sets += 1;
+            return;
+        }
+        let set = self.pending.entry(key.set).or_insert_with(|| PendingSet {
+            first_arrival: now,
+            count: key.count,
+            fragments: Vec::new(),
+        });
+        set.fragments.push(packet);
+        self.held += 1;
+    }
+}
+
+impl<S, K, R> FragmentReassemblySim<S, K, R>
+where
+    K: Hash + Eq,
+{
+    /// Wraps `conditioner`, holding at most `max_fragments` fragments and
+    /// giving each set `timeout` to complete. `key` extracts the set a
+    /// packet belongs to.
+    pub fn new(
+        conditioner: Conditioner<S, R>,
+        max_fragments: usize,
+        timeout: Duration,
+        key: impl Fn(&RecvFrom) -> Option<FragmentKey<K>> + Send + Sync + 'static,
+    ) -> Self {
+        FragmentReassemblySim {
+            conditioner,
+            key: Box::new(key),
+            max_fragments,
+            timeout,
+            state: Mutex::new(ReassemblyState {
+                pending: HashMap::new(),
+                held: 0,
+                ready: VecDeque::new(),
+                dropped_sets: 0,
+            }),
+        }
+    }
+
+    conditioner_accessors!();
+
+    /// Number of fragments held in incomplete sets.
+    pub fn pending_fragments(&self) -> usize {
+        lock(&self.state).held
+    }
+
+    /// Number of fragment sets dropped for timing out or overflowing the
+    /// buffer.
+    pub fn dropped_sets(&self) -> u64 {
+        lock(&self.state).dropped_sets
+    }
+}
+
+impl<S, K, R> fmt::Debug for FragmentReassemblySim<S, K, R>
+where
+    S: fmt::Debug,
+    K: fmt::Debug,
+    R: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FragmentReassemblySim")
+            .field("conditioner", &self.conditioner)
+            .field("max_fragments", &self.max_fragments)
+            .field("timeout", &self.timeout)
+            .field("state", &self.state)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, K, R> SocketLike for FragmentReassemblySim<S, K, R>
+where
+    S: SocketLike,
+    K: Hash + Eq,
+    R: RandomSource,
+{
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.conditioner.set_nonblocking(nonblocking)
+    }
+
+    fn is_nonblocking(&self) -> Option<bool> {
+        self.conditioner.is_nonblocking()
+    }
+
+    /// Delivers the next packet of a complete set, or one without a set,
+    /// reading from the conditioner until there is one or it would block.
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        loop {
+            {
+                let mut state = lock(&self.state);
+                state.expire(self.conditioner.clock().now(), self.timeout);
+                if let Some(packet) = state.ready.pop_front() {
+                    let len = packet.data.len().min(buf.len());
+                    buf[..len].copy_from_slice(&packet.data[..len]);
+                    return Ok((len, packet.addr));
+                }
+            }
+
+            let packet = recv_packet(&self.conditioner)?;
+            let mut state = lock(&self.state);
+            match (self.key)(&packet) {
+                Some(key) => {
+                    let now = self.conditioner.clock().now();
+                    state.add(key, packet, now, self.max_fragments);
+                }
+                None => state.ready.push_back(packet),
+            }
+        }
+    }
+
+    fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        self.conditioner.send(buf)
+    }
+
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        self.conditioner.send_to(buf, addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, time::Duration};
+
+    use rand::rngs::StdRng;
+
+    use super::{FragmentKey, FragmentReassemblySim};
+    use crate::{
+        tests::{conditioner, peer, MockSocket},
+        ConditionerConfig, SocketLike,
+    };
+
+    type Sim = FragmentReassemblySim<MockSocket, u8, StdRng>;
+
+    /// Fragments are `[set, count, index]`, empty packets have no set.
+    fn sim(max_fragments: usize) -> Sim {
+        let conditioner = conditioner(ConditionerConfig::default());
+        FragmentReassemblySim::new(
+            conditioner,
+            max_fragments,
+            Duration::from_millis(100),
+            |packet| match packet.data[..] {
+                [set, count, _] => Some(FragmentKey {
+                    set,
+                    count: count as usize,
+                }),
+                _ => None,
+            },
+        )
+    }
+
+    fn recv_all(sim: &Sim) -> Vec<Vec<u8>> {
+        let mut delivered = Vec::new();
+        let mut buf = [0; 16];
+        loop {
+            match sim.recv_from(&mut buf) {
+                Ok((len, _)) => delivered.push(buf[..len].to_vec()),
+                Err(err) => {
+                    assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+                    return delivered;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn delivers_complete_sets_in_arrival_order() {
+        let sim = sim(16);
+        sim.conditioner().inject(peer(1), vec![1, 2, 1]);
+        sim.conditioner().inject(peer(1), vec![]);
+        assert_eq!(recv_all(&sim), [vec![]]);
+        assert_eq!(sim.pending_fragments(), 1);
+
+        sim.conditioner().inject(peer(1), vec![1, 2, 0]);
+        assert_eq!(recv_all(&sim), [vec![1, 2, 1], vec![1, 2, 0]]);
+        assert_eq!(sim.pending_fragments(), 0);
+        assert_eq!(sim.dropped_sets(), 0);
+    }
+
+    #[test]
+    fn drops_incomplete_sets_after_the_timeout() {
+        let sim = sim(16);
+        sim.conditioner().inject(peer(1), vec![1, 3, 0]);
+        sim.conditioner().inject(peer(1), vec![1, 3, 1]);
+        assert!(recv_all(&sim).is_empty());
+
+        sim.conditioner().advance(Duration::from_millis(100));
+        sim.conditioner().inject(peer(1), vec![1, 3, 2]);
+        assert!(recv_all(&sim).is_empty());
+        assert_eq!(sim.dropped_sets(), 1);
+        // The late fragment started the set over.
+        assert_eq!(sim.pending_fragments(), 1);
+    }
+
+    #[test]
+    fn drops_sets_that_overflow_the_buffer() {
+        let sim = sim(2);
+        sim.conditioner().inject(peer(1), vec![1, 3, 0]);
+        sim.conditioner().inject(peer(1), vec![1, 3, 1]);
+        sim.conditioner().inject(peer(1), vec![2, 2, 0]);
+        assert!(recv_all(&sim).is_empty());
+        assert_eq!(sim.dropped_sets(), 1);
+        assert_eq!(sim.pending_fragments(), 2);
+
+        sim.conditioner().inject(peer(1), vec![3, 2, 0]);
+        sim.conditioner().inject(peer(1), vec![1, 3, 2]);
+        assert_eq!(
+            recv_all(&sim),
+            [vec![1, 3, 0], vec![1, 3, 1], vec![1, 3, 2]]
+        );
+        assert_eq!(sim.dropped_sets(), 2);
+        assert_eq!(sim.pending_fragments(), 0);
+    }
+
+    #[test]
+    fn completing_fragments_get_past_a_full_buffer() {
+        let sim = sim(1);
+        sim.conditioner().inject(peer(1), vec![1, 2, 0]);
+        sim.conditioner().inject(peer(1), vec![2, 1, 0]);
+        sim.conditioner().inject(peer(1), vec![1, 2, 1]);
+        assert_eq!(
+            recv_all(&sim),
+            [vec![2, 1, 0], vec![1, 2, 0], vec![1, 2, 1]]
+        );
+        assert_eq!(sim.dropped_sets(), 0);
+        assert_eq!(sim.pending_fragments(), 0);
+    }
+
+    #[test]
+    fn debug_skips_the_key_fn() {
+        let debug = format!("{:?}", sim(2));
+        assert!(debug.starts_with("FragmentReassemblySim { conditioner: Conditioner {"));
+        assert!(debug.contains("max_fragments: 2, timeout: 100ms"));
+        assert!(debug.ends_with(", .. }"));
+    }
+}
@@ -0,0 +1,288 @@
+//! OS socket buffer sizes, set directly through `setsockopt` on unix, the
+//! socket's blocking mode through `fcntl`, and destination addresses and
+//! DSCP of received packets through `recvmsg` on Linux.
+
+#[cfg(target_os = "linux")]
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::{io, mem, net::UdpSocket, os::unix::io::AsRawFd};
+
+use crate::Conditioner;
+#[cfg(target_os = "linux")]
+use crate::PacketInfo;
+
+impl<R> Conditioner<UdpSocket, R> {
+    /// Sets the kernel receive buffer size (`SO_RCVBUF`).
+    ///
+    /// Shrinking it makes the kernel drop packets that arrive faster than they
+    /// are read, on top of anything the conditioner simulates.
+    pub fn set_recv_buffer_size(&self, bytes: usize) -> io::Result<()> {
+        set_buffer_size(&self.socket, libc::SO_RCVBUF, bytes)
+    }
+
+    /// Kernel receive buffer size, the OS may round or double what was set.
+    pub fn recv_buffer_size(&self) -> io::Result<usize> {
+        buffer_size(&self.socket, libc::SO_RCVBUF)
+    }
+
+    /// Sets the kernel send buffer size (`SO_SNDBUF`).
+    pub fn set_send_buffer_size(&self, bytes: usize) -> io::Result<()> {
+        set_buffer_size(&self.socket, libc::SO_SNDBUF, bytes)
+    }
+
+    /// Kernel send buffer size, the OS may round or double what was set.
+    pub fn send_buffer_size(&self) -> io::Result<usize> {
+        buffer_size(&self.socket, libc::SO_SNDBUF)
+    }
+}
+
+/// Whether `O_NONBLOCK` is set on the socket.
+pub(crate) fn is_nonblocking(socket: &UdpSocket) -> io::Result<bool> {
+    let flags = unsafe { libc::fcntl(socket.as_raw_fd(), libc::F_GETFL) };
+    if flags == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(flags & libc::O_NONBLOCK != 0)
+}
+
+fn set_buffer_size(socket: &UdpSocket, option: libc::c_int, bytes: usize) -> io::Result<()> {
+    let value = libc::c_int::try_from(bytes)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "buffer size too large"))?;
+    set_option(socket, libc::SOL_SOCKET, option, value)
+}
+
+fn set_option(
+    socket: &UdpSocket,
+    level: libc::c_int,
+    option: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            option,
+            &value as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn buffer_size(socket: &UdpSocket, option: libc::c_int) -> io::Result<usize> {
+    let mut value: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            option,
+            &mut value as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value as usize)
+}
+
+/// Makes the kernel attach the destination address and TOS/traffic class to
+/// received packets, read back by [`recv_from_info`].
+#[cfg(target_os = "linux")]
+pub(crate) fn enable_packet_info(socket: &UdpSocket) -> io::Result<()> {
+    if socket.local_addr()?.is_ipv4() {
+        set_option(socket, libc::IPPROTO_IP, libc::IP_PKTINFO, 1)?;
+        set_option(socket, libc::IPPROTO_IP, libc::IP_RECVTOS, 1)
+    } else {
+        set_option(socket, libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO, 1)?;
+        set_option(socket, libc::IPPROTO_IPV6, libc::IPV6_RECVTCLASS, 1)
+    }
+}
+
+/// Receives a datagram along with where it was sent to and its DSCP, left
+/// unset if the kernel didn't attach them because [`enable_packet_info`]
+/// wasn't called.
+#[cfg(target_os = "linux")]
+pub(crate) fn recv_from_info(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, PacketInfo)> {
+    let mut source: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    // Room for a pktinfo and a TOS/traffic class, aligned for `cmsghdr`.
+    let mut control = [0u64; 16];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut source as *mut libc::sockaddr_storage as *mut libc::c_void;
+    msg.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    let received = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+    if received == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let addr = match source.ss_family as libc::c_int {
+        libc::AF_INET => {
+            let source = unsafe { *(&source as *const _ as *const libc::sockaddr_in) };
+            SocketAddr::V4(SocketAddrV4::new(
+                Ipv4Addr::from(u32::from_be(source.sin_addr.s_addr)),
+                u16::from_be(source.sin_port),
+            ))
+        }
+        libc::AF_INET6 => {
+            let source = unsafe { *(&source as *const _ as *const libc::sockaddr_in6) };
+            SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::from(source.sin6_addr.s6_addr),
+                u16::from_be(source.sin6_port),
+                u32::from_be(source.sin6_flowinfo),
+                source.sin6_scope_id,
+            ))
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsupported source address family",
+            ))
+        }
+    };
+
+    let mut info = PacketInfo::default();
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while !cmsg.is_null() {
+        let header = unsafe { *cmsg };
+        match (header.cmsg_level, header.cmsg_type) {
+            (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+                let pktinfo = unsafe { *(libc::CMSG_DATA(cmsg) as *const libc::in_pktinfo) };
+                info.destination = Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                    pktinfo.ipi_addr.s_addr,
+                ))));
+            }
+            (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+                let pktinfo = unsafe { *(libc::CMSG_DATA(cmsg) as *const libc::in6_pktinfo) };
+                info.destination = Some(IpAddr::V6(Ipv6Addr::from(pktinfo.ipi6_addr.s6_addr)));
+            }
+            // The DSCP is the upper six bits, the rest is ECN.
+            (libc::IPPROTO_IP, libc::IP_TOS) => {
+                let tos = unsafe { *libc::CMSG_DATA(cmsg) };
+                info.dscp = Some(tos >> 2);
+            }
+            (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => {
+                let class = unsafe { *(libc::CMSG_DATA(cmsg) as *const libc::c_int) };
+                info.dscp = Some((class >> 2) as u8 & 0x3f);
+            }
+            _ => {}
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+    Ok((received as usize, addr, info))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+
+    use crate::{Conditioner, ConditionerConfig, SocketLike};
+
+    fn conditioner() -> Conditioner<UdpSocket> {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        Conditioner::new(ConditionerConfig::default(), socket)
+    }
+
+    #[test]
+    fn sets_buffer_sizes() {
+        // The OS may round or, like Linux, double what is set.
+        let conditioner = conditioner();
+        conditioner.set_recv_buffer_size(64 * 1024).unwrap();
+        let recv = conditioner.recv_buffer_size().unwrap();
+        assert!((64 * 1024..=128 * 1024).contains(&recv), "{}", recv);
+
+        conditioner.set_send_buffer_size(32 * 1024).unwrap();
+        let send = conditioner.send_buffer_size().unwrap();
+        assert!((32 * 1024..=64 * 1024).contains(&send), "{}", send);
+    }
+
+    #[test]
+    fn rejects_oversized_buffer() {
+        let err = conditioner().set_recv_buffer_size(usize::MAX).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn reports_blocking_mode() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert!(!super::is_nonblocking(&socket).unwrap());
+        socket.set_nonblocking(true).unwrap();
+        assert!(super::is_nonblocking(&socket).unwrap());
+    }
+
+    #[test]
+    fn wrapping_keeps_socket_options() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        super::set_buffer_size(&socket, libc::SO_RCVBUF, 48 * 1024).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let recv = super::buffer_size(&socket, libc::SO_RCVBUF).unwrap();
+
+        let conditioner = Conditioner::new(ConditionerConfig::default(), socket);
+        assert_eq!(conditioner.recv_buffer_size().unwrap(), recv);
+        assert_eq!(conditioner.is_nonblocking(), Some(true));
+    }
+
+    #[cfg(feature = "socket2")]
+    #[test]
+    fn from_socket2_keeps_socket_options() {
+        use socket2::{Domain, Socket, Type};
+
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, None).unwrap();
+        socket.set_recv_buffer_size(48 * 1024).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let recv = socket.recv_buffer_size().unwrap();
+        socket
+            .bind(
+                &"127.0.0.1:0"
+                    .parse::<std::net::SocketAddr>()
+                    .unwrap()
+                    .into(),
+            )
+            .unwrap();
+        let local = socket.local_addr().unwrap().as_socket();
+
+        let conditioner = Conditioner::from_socket2(ConditionerConfig::default(), socket).unwrap();
+        assert_eq!(conditioner.recv_buffer_size().unwrap(), recv);
+        assert_eq!(conditioner.is_nonblocking(), Some(true));
+        assert_eq!(Some(conditioner.local_addr().unwrap()), local);
+
+        let stream = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+        let err = Conditioner::from_socket2(ConditionerConfig::default(), stream).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn reads_the_destination_address() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut buf = [0; 16];
+
+        sender.send_to(&[1], socket.local_addr().unwrap()).unwrap();
+        let (_, _, info) = super::recv_from_info(&socket, &mut buf).unwrap();
+        assert_eq!(info.destination, None);
+
+        super::enable_packet_info(&socket).unwrap();
+        sender.send_to(&[2], socket.local_addr().unwrap()).unwrap();
+        let (len, addr, info) = super::recv_from_info(&socket, &mut buf).unwrap();
+        assert_eq!(
+            (&buf[..len], addr),
+            (&[2][..], sender.local_addr().unwrap())
+        );
+        assert_eq!(info.destination, Some([127, 0, 0, 1].into()));
+    }
+}
@@ -0,0 +1,435 @@
+//! Counters describing what the conditioner did to the packets it saw.
+
+use std::{collections::BTreeMap, fmt::Write, net::SocketAddr, time::Duration};
+
+/// Running totals for a conditioner, see [`Conditioner::stats`](crate::Conditioner::stats).
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ConditionerStats {
+    /// Packets read from the underlying socket.
+    pub packets_received: u64,
+    /// Packets discarded because their source isn't on the allowlist, not
+    /// included in `packets_received`.
+    pub packets_filtered: u64,
+    /// Packets put in the delivery queue.
+    pub packets_queued: u64,
+    /// Packets handed back to the caller.
+    pub packets_delivered: u64,
+    /// Bytes currently sitting in the delivery queue.
+    pub bytes_currently_queued: u64,
+    /// Bytes of every packet handed back to the caller, before any
+    /// truncation to the caller's buffer.
+    pub bytes_delivered_total: u64,
+    /// Packets that had a bit flipped.
+    pub packets_corrupted: u64,
+    /// Extra copies of packets queued by `duplicate_chance`.
+    pub packets_duplicated: u64,
+    /// Packets held back so later packets overtake them.
+    pub packets_reordered: u64,
+    /// Packets dropped by the flat `packet_loss` roll.
+    pub random_loss_drops: u64,
+    /// Packets dropped by the extra loss from `congestion`.
+    pub congestion_drops: u64,
+    /// Packets dropped while the link was down, whether they were arriving,
+    /// already queued or being sent.
+    pub link_down_drops: u64,
+    /// Packets dropped on their way out for having been queued longer than
+    /// `max_queue_age`.
+    pub packets_expired: u64,
+    /// Packets dropped on arrival because the queue already held
+    /// `max_queue_len` packets.
+    pub queue_overflow_drops: u64,
+    pub(crate) drop_bursts: BTreeMap<u64, u64>,
+    /// Scheduled delays of queued packets.
+    pub(crate) delays: DelayHistogram,
+    pub(crate) peer_packets: BTreeMap<SocketAddr, u64>,
+    /// Packets whose delay was cut down to `max_total_delay`.
+    pub delays_clamped: u64,
+    /// Delivered packets that spent longer than `sla_max_latency` between
+    /// arriving and being handed back.
+    pub sla_breaches: u64,
+    /// Packets that would have been dropped while in dry-run mode.
+    pub would_drop: u64,
+    /// Total delay that would have been applied while in dry-run mode.
+    pub would_delay: Duration,
+    /// Packets waiting in the queue when the snapshot was taken.
+    pub queue_len: usize,
+}
+
+impl ConditionerStats {
+    /// Packets dropped for any reason.
+    pub fn total_drops(&self) -> u64 {
+        self.random_loss_drops
+            + self.congestion_drops
+            + self.link_down_drops
+            + self.packets_expired
+            + self.queue_overflow_drops
+    }
+
+    /// How many runs of each length of consecutive drops were seen, keyed by
+    /// run length.
+    ///
+    /// A run is only counted once a packet is kept after it, so a run still
+    /// in progress isn't included yet.
+    pub fn drop_burst_histogram(&self) -> &BTreeMap<u64, u64> {
+        &self.drop_bursts
+    }
+
+    /// Packets received from each source, dropped ones included.
+    pub fn packets_by_peer(&self) -> &BTreeMap<SocketAddr, u64> {
+        &self.peer_packets
+    }
+
+    pub(crate) fn record_delay(&mut self, delay: Duration) {
+        self.delays.record(delay);
+    }
+
+    /// Average delay scheduled for queued packets, `None` before any were
+    /// queued.
+    pub fn mean_delay(&self) -> Option<Duration> {
+        self.delays.mean()
+    }
+
+    /// Delay at or below which `percentile` (in `0.0 ..= 1.0`) of the queued
+    /// packets were scheduled.
+    ///
+    /// Delays are counted in log-scale buckets, so this is exact below 16µs
+    /// and otherwise up to 1/8 over, though never over the longest delay
+    /// seen.
+    pub fn delay_percentile(&self, percentile: f32) -> Option<Duration> {
+        self.delays.percentile(percentile)
+    }
+
+    /// Human readable overview: totals, drops by reason, delay percentiles
+    /// and per-peer counts.
+    pub fn summary(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "packets: {} received, {} filtered, {} queued, {} delivered",
+            self.packets_received,
+            self.packets_filtered,
+            self.packets_queued,
+            self.packets_delivered
+        );
+        let _ = writeln!(
+            out,
+            "drops: {} total, {} random loss, {} congestion, {} link down, {} expired, {} queue overflow",
+            self.total_drops(),
+            self.random_loss_drops,
+            self.congestion_drops,
+            self.link_down_drops,
+            self.packets_expired,
+            self.queue_overflow_drops
+        );
+        match self.mean_delay() {
+            Some(mean) => {
+                let _ = writeln!(
+                    out,
+                    "delay: mean {:?}, p50 {:?}, p90 {:?}, p99 {:?}",
+                    mean,
+                    self.delay_percentile(0.5).unwrap_or_default(),
+                    self.delay_percentile(0.9).unwrap_or_default(),
+                    self.delay_percentile(0.99).unwrap_or_default()
+                );
+            }
+            None => {
+                let _ = writeln!(out, "delay: no packets queued");
+            }
+        }
+        for (peer, packets) in &self.peer_packets {
+            let _ = writeln!(out, "peer {}: {} packets", peer, packets);
+        }
+        out
+    }
+}
+
+/// Each power of two of microseconds is split into this many buckets.
+const SUB_BUCKETS: u64 = 8;
+/// Delays under this many microseconds get a bucket each.
+const LINEAR_MICROS: u64 = 2 * SUB_BUCKETS;
+/// Enough buckets for any delay up to `u64::MAX` microseconds.
+const DELAY_BUCKETS: usize = (LINEAR_MICROS + (64 - 4) * SUB_BUCKETS) as usize;
+
+/// Delays counted in fixed log-scale buckets, so it stays the same size
+/// however many distinct delays are recorded. The mean, shortest and
+/// longest delay are kept exactly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DelayHistogram {
+    buckets: [u64; DELAY_BUCKETS],
+    count: u64,
+    total_nanos: u128,
+    min: Duration,
+    max: Duration,
+}
+
+impl Default for DelayHistogram {
+    fn default() -> Self {
+        DelayHistogram {
+            buckets: [0; DELAY_BUCKETS],
+            count: 0,
+            total_nanos: 0,
+            min: Duration::MAX,
+            max: Duration::ZERO,
+        }
+    }
+}
+
+impl DelayHistogram {
+    fn record(&mut self, delay: Duration) {
+        let micros = u64::try_from(delay.as_micros()).unwrap_or(u64::MAX);
+        self.buckets[bucket(micros)] += 1;
+        self.count += 1;
+        self.total_nanos = self.total_nanos.saturating_add(delay.as_nanos());
+        self.min = self.min.min(delay);
+        self.max = self.max.max(delay);
+    }
+
+    fn mean(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        // Never more than the longest delay, so the seconds fit a `u64`.
+        let mean = self.total_nanos / self.count as u128;
+        let secs = u64::try_from(mean / 1_000_000_000).unwrap_or(u64::MAX);
+        Some(Duration::new(secs, (mean % 1_000_000_000) as u32))
+    }
+
+    fn percentile(&self, percentile: f32) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((self.count as f64 * percentile.clamp(0.0, 1.0) as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        let index = self.buckets.iter().position(|n| {
+            seen += n;
+            seen >= rank
+        })?;
+        let upper = Duration::from_micros(bucket_upper_bound(index));
+        Some(upper.clamp(self.min, self.max))
+    }
+}
+
+/// Bucket counting delays of `micros` microseconds.
+fn bucket(micros: u64) -> usize {
+    if micros < LINEAR_MICROS {
+        return micros as usize;
+    }
+    let octave = 63 - micros.leading_zeros() as u64;
+    let sub = (micros >> (octave - 3)) - SUB_BUCKETS;
+    (LINEAR_MICROS + (octave - 4) * SUB_BUCKETS + sub) as usize
+}
+
+/// Longest delay in microseconds counted by bucket `index`.
+fn bucket_upper_bound(index: usize) -> u64 {
+    let index = index as u64;
+    if index < LINEAR_MICROS {
+        return index;
+    }
+    let octave = (index - LINEAR_MICROS) / SUB_BUCKETS + 4;
+    let sub = (index - LINEAR_MICROS) % SUB_BUCKETS;
+    let width = 1 << (octave - 3);
+    ((SUB_BUCKETS + sub) << (octave - 3)).saturating_add(width - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::ConditionerStats;
+    use crate::{
+        congestion::Congestion,
+        random::tests::LossRolls,
+        tests::{conditioner, peer, recv_all, MockSocket},
+        Conditioner, ConditionerConfig,
+    };
+
+    /// Injects 10 packets, sends them on their way and returns the stats.
+    fn drops(config: ConditionerConfig, link_down: bool) -> ConditionerStats {
+        let conditioner = conditioner(config);
+        conditioner.set_link_down(link_down);
+        for _ in 0..10 {
+            conditioner.inject(peer(1), vec![0]);
+        }
+        conditioner.advance(Duration::from_secs(1));
+        recv_all(&conditioner);
+        conditioner.stats()
+    }
+
+    #[test]
+    fn bytes_queued_and_delivered() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        conditioner.inject(peer(1), vec![0; 100]);
+        conditioner.inject(peer(1), vec![0; 20]);
+        assert_eq!(conditioner.stats().bytes_currently_queued, 120);
+        assert_eq!(conditioner.stats().bytes_delivered_total, 0);
+
+        conditioner.advance(Duration::from_millis(10));
+        recv_all(&conditioner);
+        let stats = conditioner.stats();
+        assert_eq!(stats.bytes_currently_queued, 0);
+        assert_eq!(stats.bytes_delivered_total, 120);
+    }
+
+    #[test]
+    fn random_loss_drops() {
+        let config = ConditionerConfig::builder()
+            .packet_loss_ratio(1.0)
+            .build()
+            .unwrap();
+        let stats = drops(config, false);
+        assert_eq!(stats.random_loss_drops, 10);
+        assert_eq!(stats.total_drops(), 10);
+    }
+
+    #[test]
+    fn congestion_drops() {
+        let config = ConditionerConfig::builder()
+            .congestion(Congestion {
+                window: Duration::from_secs(1),
+                threshold: 0.0,
+                factor: 1.0,
+            })
+            .build()
+            .unwrap();
+        let stats = drops(config, false);
+        assert_eq!(stats.congestion_drops, 10);
+        assert_eq!(stats.total_drops(), 10);
+    }
+
+    #[test]
+    fn link_down_drops() {
+        let stats = drops(ConditionerConfig::default(), true);
+        assert_eq!(stats.link_down_drops, 10);
+        assert_eq!(stats.total_drops(), 10);
+    }
+
+    #[test]
+    fn expired_drops() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(100))
+            .max_queue_age(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        let stats = drops(config, false);
+        assert_eq!(stats.packets_expired, 10);
+        assert_eq!(stats.total_drops(), 10);
+    }
+
+    #[test]
+    fn queue_overflow_drops() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(10))
+            .max_queue_len(4)
+            .build()
+            .unwrap();
+        let stats = drops(config, false);
+        assert_eq!(stats.queue_overflow_drops, 6);
+        assert_eq!(stats.packets_delivered, 4);
+        assert_eq!(stats.total_drops(), 6);
+    }
+
+    #[test]
+    fn drop_burst_histogram_counts_closed_runs() {
+        let config = ConditionerConfig::builder()
+            .packet_loss_ratio(0.5)
+            .build()
+            .unwrap();
+        // Runs of 3 and 1 drops, each closed by a kept packet, then a run of
+        // 2 still in progress.
+        let random = LossRolls {
+            loss: vec![0.1, 0.1, 0.1, 0.9, 0.1, 0.9, 0.1, 0.1],
+            drawn: 0,
+        };
+        let conditioner = Conditioner::with_random(config, MockSocket::default(), random);
+        for _ in 0..8 {
+            conditioner.inject(peer(1), vec![0]);
+        }
+        let stats = conditioner.stats();
+        assert_eq!(
+            stats.drop_burst_histogram().iter().collect::<Vec<_>>(),
+            [(&1, &1), (&3, &1)]
+        );
+        assert_eq!(stats.random_loss_drops, 6);
+    }
+
+    #[test]
+    fn delay_mean_and_percentiles() {
+        let mut stats = ConditionerStats::default();
+        assert_eq!(stats.mean_delay(), None);
+        assert_eq!(stats.delay_percentile(0.5), None);
+        for ms in 1..=10 {
+            stats.record_delay(Duration::from_millis(ms));
+        }
+        assert_eq!(stats.mean_delay(), Some(Duration::from_micros(5500)));
+        // Bucketed, so up to 1/8 over the exact value.
+        for (percentile, ms) in [(0.0, 1), (0.5, 5), (0.9, 9)] {
+            let delay = stats.delay_percentile(percentile).unwrap();
+            let exact = Duration::from_millis(ms);
+            assert!(delay >= exact && delay <= exact * 9 / 8, "{:?}", delay);
+        }
+        assert_eq!(stats.delay_percentile(1.0), Some(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn delay_buckets_cover_every_delay() {
+        for micros in (0..5000).chain([u64::MAX / 3, u64::MAX - 1, u64::MAX]) {
+            let index = super::bucket(micros);
+            assert!(super::bucket_upper_bound(index) >= micros);
+            assert!(index == 0 || super::bucket_upper_bound(index - 1) < micros);
+        }
+        assert_eq!(super::bucket(u64::MAX), super::DELAY_BUCKETS - 1);
+    }
+
+    #[test]
+    fn extreme_delays_dont_overflow() {
+        let mut stats = ConditionerStats::default();
+        stats.record_delay(Duration::MAX);
+        stats.record_delay(Duration::MAX);
+        assert_eq!(stats.delay_percentile(0.5), Some(Duration::MAX));
+        assert_eq!(stats.mean_delay(), Some(Duration::MAX));
+
+        let mut stats = ConditionerStats::default();
+        stats.delays.count = 1 << 32;
+        stats.delays.total_nanos = 1 << 32;
+        assert_eq!(stats.mean_delay(), Some(Duration::from_nanos(1)));
+    }
+
+    #[test]
+    fn summary_includes_drops_and_peers() {
+        let config = ConditionerConfig::builder()
+            .latency(Duration::from_millis(10))
+            .packet_loss_ratio(0.5)
+            .build()
+            .unwrap();
+        let conditioner = conditioner(config);
+        for port in [1, 1, 2] {
+            for _ in 0..10 {
+                conditioner.inject(peer(port), vec![0]);
+            }
+        }
+        let stats = conditioner.stats();
+        let summary = stats.summary();
+        let drops = format!(
+            "drops: {} total, {} random loss, 0 congestion, 0 link down, 0 expired, 0 queue overflow",
+            stats.random_loss_drops, stats.random_loss_drops
+        );
+        assert!(stats.random_loss_drops > 0);
+        assert!(summary.lines().any(|line| line == drops), "{}", summary);
+        assert!(summary.contains("packets: 30 received"), "{}", summary);
+        assert!(summary.contains("delay: mean 10ms"), "{}", summary);
+        assert!(
+            summary.contains("peer 127.0.0.1:1: 20 packets"),
+            "{}",
+            summary
+        );
+        assert!(
+            summary.contains("peer 127.0.0.1:2: 10 packets"),
+            "{}",
+            summary
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! Helpers for exercising a conditioner against real sockets in tests.
+
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{random::RandomSource, Conditioner, SocketLike};
+
+/// How long [`measure_rtt`] waits for each reply before counting it as lost.
+pub const RTT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Sends `samples` pings through `conditioner` to an echo `peer`, one at a
+/// time, and returns the round-trip time of each reply.
+///
+/// Times are measured on the conditioner's clock. Pings whose reply doesn't
+/// come back within [`RTT_TIMEOUT`] of real time are left out, so the result
+/// may be shorter than `samples`, e.g. empty on a mock clock nobody advances.
+/// The conditioner's socket should be nonblocking.
+///
+/// Only the replies are delayed and rolled for loss, the pings go out right
+/// away, subject only to the send-side checks. So against a local echo
+/// server the RTT is roughly the configured latency.
+///
+/// ```
+/// use std::{net::UdpSocket, time::Duration};
+///
+/// use link_conditioner::{
+///     test_util::{measure_rtt, EchoServer},
+///     Conditioner, ConditionerConfig, SocketLike,
+/// };
+///
+/// let echo = EchoServer::spawn()?;
+/// let latency = Duration::from_millis(20);
+/// let config = ConditionerConfig::builder().latency(latency).build().unwrap();
+/// let conditioner = Conditioner::new(config, UdpSocket::bind("127.0.0.1:0")?);
+/// conditioner.set_nonblocking(true)?;
+///
+/// let rtts = measure_rtt(&conditioner, echo.addr(), 5)?;
+/// assert_eq!(rtts.len(), 5);
+/// let mean = rtts.iter().sum::<Duration>() / rtts.len() as u32;
+/// assert!(mean >= latency && mean < latency * 3);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn measure_rtt<S, R>(
+    conditioner: &Conditioner<S, R>,
+    peer: SocketAddr,
+    samples: usize,
+) -> io::Result<Vec<Duration>>
+where
+    S: SocketLike,
+    R: RandomSource,
+{
+    let mut rtts = Vec::with_capacity(samples);
+    let mut buf = [0; 8];
+    for sample in 0..samples as u64 {
+        let sent = conditioner.clock().now();
+        let deadline = Instant::now() + RTT_TIMEOUT;
+        conditioner.send_to(&sample.to_le_bytes(), peer)?;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match conditioner.recv_from_blocking(&mut buf, Some(remaining)) {
+                Ok((8, from)) if from == peer => {
+                    // Replies to earlier, timed out pings may still trickle in.
+                    if u64::from_le_bytes(buf) == sample {
+                        rtts.push(conditioner.clock().now() - sent);
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(err) if err.kind() == io::ErrorKind::TimedOut => break,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+    Ok(rtts)
+}
+
+/// UDP echo server on a background thread, bound to an ephemeral loopback port.
+///
+/// Every datagram received is sent straight back to its source. The server
+/// stops when dropped.
+///
+/// ```
+/// use std::{net::UdpSocket, time::{Duration, Instant}};
+///
+/// use link_conditioner::{test_util::EchoServer, Conditioner, ConditionerConfig, SocketLike};
+///
+/// let echo = EchoServer::spawn()?;
+/// let latency = Duration::from_millis(20);
+/// let config = ConditionerConfig::builder().latency(latency).build().unwrap();
+/// let conditioner = Conditioner::new(config, UdpSocket::bind("127.0.0.1:0")?);
+/// conditioner.set_nonblocking(true)?;
+///
+/// let sent = Instant::now();
+/// conditioner.send_to(b"ping", echo.addr())?;
+/// let mut buf = [0; 16];
+/// let (len, from) = conditioner.recv_from_blocking(&mut buf, Some(Duration::from_secs(1)))?;
+/// assert_eq!((&buf[..len], from), (&b"ping"[..], echo.addr()));
+/// assert!(sent.elapsed() >= latency);
+/// echo.stop();
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct EchoServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl EchoServer {
+    pub fn spawn() -> io::Result<Self> {
+        let socket = UdpSocket::bind(("127.0.0.1", 0))?;
+        // Wake up periodically to check the stop flag.
+        socket.set_read_timeout(Some(Duration::from_millis(10)))?;
+        let addr = socket.local_addr()?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let handle = thread::spawn(move || {
+            let mut buf = [0; 16384];
+            while !thread_stop.load(Ordering::Relaxed) {
+                if let Ok((received, from)) = socket.recv_from(&mut buf) {
+                    let _ = socket.send_to(&buf[..received], from);
+                }
+            }
+        });
+
+        Ok(EchoServer {
+            addr,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// Address to send datagrams to.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stops the server and waits for its thread to exit.
+    pub fn stop(self) {}
+}
+
+impl Drop for EchoServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::{Clock, MockClock},
+        ConditionerConfig,
+    };
+
+    fn conditioner(latency: Duration) -> Conditioner<UdpSocket> {
+        let config = ConditionerConfig::builder()
+            .latency(latency)
+            .build()
+            .unwrap();
+        let mut conditioner = Conditioner::new(config, UdpSocket::bind("127.0.0.1:0").unwrap());
+        conditioner.set_nonblocking(true).unwrap();
+        conditioner.set_clock(Clock::Mock(MockClock::new()));
+        conditioner
+    }
+
+    #[test]
+    fn measure_rtt_on_the_mock_clock() {
+        let echo = EchoServer::spawn().unwrap();
+        let conditioner = conditioner(Duration::ZERO);
+
+        let rtts = measure_rtt(&conditioner, echo.addr(), 3).unwrap();
+        assert_eq!(rtts, [Duration::ZERO; 3]);
+    }
+
+    #[test]
+    fn measure_rtt_gives_up_on_a_frozen_mock_clock() {
+        let echo = EchoServer::spawn().unwrap();
+        let conditioner = conditioner(Duration::from_millis(10));
+
+        let started = Instant::now();
+        let rtts = measure_rtt(&conditioner, echo.addr(), 1).unwrap();
+        assert!(rtts.is_empty());
+        assert!(started.elapsed() >= RTT_TIMEOUT);
+    }
+
+    #[test]
+    fn echo_server_stops() {
+        let echo = EchoServer::spawn().unwrap();
+        let addr = echo.addr();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let mut buf = [0; 16];
+
+        socket.send_to(b"ping", addr).unwrap();
+        assert_eq!(socket.recv_from(&mut buf).unwrap(), (4, addr));
+
+        echo.stop();
+        socket.send_to(b"ping", addr).unwrap();
+        assert!(socket.recv_from(&mut buf).is_err());
+    }
+}
@@ -1,58 +1,116 @@
 // Swiped from naia's link conditioner.
 
-use std::{cmp::Ordering, collections::BinaryHeap, fmt::Debug, time::Instant};
+use std::{cmp::Ordering, collections::BinaryHeap, time::Instant};
 
 /// A queue for items marked by time, will only ever pop items from the queue if
-/// the time
+/// their time has come
 #[derive(Debug, Clone)]
-pub struct TimeQueue<T: Debug + Eq + PartialEq> {
+pub struct TimeQueue<T> {
     queue: BinaryHeap<ItemContainer<T>>,
+    next_sequence: u64,
 }
 
-impl<T: Debug + Eq + PartialEq> Default for TimeQueue<T> {
+impl<T> Default for TimeQueue<T> {
     fn default() -> Self {
         Self {
             queue: BinaryHeap::default(),
+            next_sequence: 0,
         }
     }
 }
 
-impl<T: Debug + Eq + PartialEq> TimeQueue<T> {
+impl<T> TimeQueue<T> {
     pub fn new() -> Self {
         Self::default()
     }
 
-    /// Adds an item to the queue marked by time
+    /// Adds an item to the queue marked by time, items marked with the same time
+    /// are popped in the order they were added
     pub fn add_item(&mut self, instant: Instant, item: T) {
-        self.queue.push(ItemContainer { instant, item });
+        self.add_item_with_priority(instant, 0, item);
+    }
+
+    /// Adds an item to the queue marked by time, items marked with the same time
+    /// are popped highest priority first, then in the order they were added
+    pub fn add_item_with_priority(&mut self, instant: Instant, priority: u8, item: T) {
+        self.add_item_in_slot(instant, instant, priority, item);
+    }
+
+    /// Adds an item to the queue marked by time, ordered by `slot` rather than
+    /// its time, e.g. the start of a window of instants. Items in the same slot
+    /// are popped highest priority first, then by time, then in the order they
+    /// were added
+    ///
+    /// An item is still only popped once its time has come, holding back the
+    /// items ordered after it until then
+    pub fn add_item_in_slot(&mut self, instant: Instant, slot: Instant, priority: u8, item: T) {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.queue.push(ItemContainer {
+            instant,
+            slot,
+            priority,
+            sequence,
+            item,
+        });
     }
 
     /// Returns whether or not there is an item that is ready to be returned
     pub fn has_item(&self) -> bool {
+        self.has_item_at(Instant::now())
+    }
+
+    /// Returns whether or not there is an item that is ready to be returned at `now`
+    pub fn has_item_at(&self, now: Instant) -> bool {
         if self.queue.is_empty() {
             return false;
         }
         if let Some(item) = self.queue.peek() {
-            return item.instant <= Instant::now();
+            return item.instant <= now;
         }
         false
     }
 
     /// Pops an item from the queue if the sufficient time has elapsed
     pub fn pop_item(&mut self) -> Option<T> {
-        if self.has_item() {
-            if let Some(container) = self.queue.pop() {
-                return Some(container.item);
-            }
+        self.pop_item_at(Instant::now())
+    }
+
+    /// Pops an item from the queue if it is ready at `now`
+    pub fn pop_item_at(&mut self, now: Instant) -> Option<T> {
+        self.pop_entry_at(now).map(|container| container.item)
+    }
+
+    /// Pops the top level item container regardless of time
+    pub fn pop_next_entry(&mut self) -> Option<ItemContainer<T>> {
+        self.queue.pop()
+    }
+
+    /// Pops the top level item container if it is ready at `now`
+    pub fn pop_entry_at(&mut self, now: Instant) -> Option<ItemContainer<T>> {
+        if self.has_item_at(now) {
+            return self.queue.pop();
         }
         None
     }
 
+    /// Peeks at the item that will be popped next, whether or not it is ready
+    pub fn peek(&self) -> Option<&T> {
+        self.queue.peek().map(|entry| &entry.item)
+    }
+
     /// Peeks at the top level item container on the queue
     pub fn peek_entry(&self) -> Option<&ItemContainer<T>> {
         self.queue.peek()
     }
 
+    /// Iterates over the queued items in delivery order, regardless of time
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        let mut entries: Vec<&ItemContainer<T>> = self.queue.iter().collect();
+        entries.sort_by(|a, b| b.cmp(a));
+        entries.into_iter().map(|entry| &entry.item)
+    }
+
     /// Returns the length of the underlying queue
     pub fn len(&self) -> usize {
         self.queue.len()
@@ -62,22 +120,212 @@ impl<T: Debug + Eq + PartialEq> TimeQueue<T> {
     pub fn is_empty(&self) -> bool {
         self.queue.is_empty()
     }
+
+    /// Removes every item from the queue
+    pub fn clear(&mut self) {
+        self.queue.clear();
+    }
+}
+
+impl<T> IntoIterator for TimeQueue<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    /// Consumes the queue, yielding every item in delivery order regardless of time
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter(self.queue.into_sorted_vec().into_iter().rev())
+    }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
-pub struct ItemContainer<T: Debug + Eq + PartialEq> {
+/// Owning iterator over a [`TimeQueue`] in delivery order
+#[derive(Debug)]
+pub struct IntoIter<T>(std::iter::Rev<std::vec::IntoIter<ItemContainer<T>>>);
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.0.next().map(|entry| entry.item)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ItemContainer<T> {
     pub instant: Instant,
+    /// What the item is ordered by, its instant unless it was added with
+    /// [`TimeQueue::add_item_in_slot`]
+    pub slot: Instant,
+    /// Breaks ties between items in the same slot, higher goes first
+    pub priority: u8,
+    /// Insertion order, breaks ties between items with the same slot, priority
+    /// and instant
+    pub sequence: u64,
     pub item: T,
 }
 
-impl<T: Debug + Eq + PartialEq> Ord for ItemContainer<T> {
+impl<T> PartialEq for ItemContainer<T> {
+    fn eq(&self, other: &ItemContainer<T>) -> bool {
+        self.slot == other.slot
+            && self.instant == other.instant
+            && self.priority == other.priority
+            && self.sequence == other.sequence
+    }
+}
+
+impl<T> Eq for ItemContainer<T> {}
+
+impl<T> Ord for ItemContainer<T> {
     fn cmp(&self, other: &ItemContainer<T>) -> Ordering {
-        other.instant.cmp(&self.instant)
+        other
+            .slot
+            .cmp(&self.slot)
+            .then_with(|| self.priority.cmp(&other.priority))
+            .then_with(|| other.instant.cmp(&self.instant))
+            .then_with(|| other.sequence.cmp(&self.sequence))
     }
 }
 
-impl<T: Debug + Eq + PartialEq> PartialOrd for ItemContainer<T> {
+impl<T> PartialOrd for ItemContainer<T> {
     fn partial_cmp(&self, other: &ItemContainer<T>) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn slot_groups_items_for_priority() {
+        let now = Instant::now();
+        let ms = Duration::from_millis(1);
+        let mut queue = TimeQueue::new();
+        queue.add_item_in_slot(now + ms, now, 0, "low");
+        queue.add_item_in_slot(now + 2 * ms, now, 46, "high");
+        queue.add_item_in_slot(now + 3 * ms, now + 3 * ms, 46, "next slot");
+
+        assert_eq!(queue.pop_item_at(now + ms), None);
+        assert_eq!(queue.pop_item_at(now + 2 * ms), Some("high"));
+        assert_eq!(queue.pop_item_at(now + 2 * ms), Some("low"));
+        assert_eq!(queue.pop_item_at(now + 3 * ms), Some("next slot"));
+    }
+
+    #[test]
+    fn equal_instants_pop_in_arrival_order() {
+        let now = Instant::now();
+        let mut queue = TimeQueue::new();
+        queue.add_item(now, vec![9, 9]);
+        queue.add_item(now, vec![1]);
+        queue.add_item(now, vec![5]);
+
+        assert_eq!(queue.pop_item_at(now), Some(vec![9, 9]));
+        assert_eq!(queue.pop_item_at(now), Some(vec![1]));
+        assert_eq!(queue.pop_item_at(now), Some(vec![5]));
+    }
+
+    #[test]
+    fn len_and_is_empty() {
+        let now = Instant::now();
+        let mut queue = TimeQueue::new();
+        assert!(queue.is_empty());
+        assert_eq!(queue.len(), 0);
+        queue.add_item(now, 1);
+        queue.add_item(now + Duration::from_secs(1), 2);
+        assert!(!queue.is_empty());
+        assert_eq!(queue.len(), 2);
+    }
+
+    #[test]
+    fn peek_ignores_time() {
+        let now = Instant::now();
+        let mut queue = TimeQueue::new();
+        assert_eq!(queue.peek(), None);
+        queue.add_item(now + Duration::from_secs(2), 2);
+        queue.add_item(now + Duration::from_secs(1), 1);
+        assert_eq!(queue.peek(), Some(&1));
+        assert_eq!(queue.pop_item_at(now), None);
+        assert_eq!(queue.len(), 2);
+    }
+
+    #[test]
+    fn clear_empties_the_queue() {
+        let now = Instant::now();
+        let mut queue = TimeQueue::new();
+        queue.add_item(now, 1);
+        queue.add_item(now, 2);
+        queue.clear();
+        assert!(queue.is_empty());
+        assert_eq!(queue.pop_item_at(now), None);
+    }
+
+    #[test]
+    fn iter_in_delivery_order() {
+        let now = Instant::now();
+        let mut queue = TimeQueue::new();
+        queue.add_item(now + Duration::from_secs(3), 3);
+        queue.add_item(now + Duration::from_secs(1), 1);
+        queue.add_item(now + Duration::from_secs(2), 2);
+        queue.add_item(now + Duration::from_secs(1), 4);
+        assert_eq!(queue.iter().copied().collect::<Vec<_>>(), [1, 4, 2, 3]);
+        assert_eq!(queue.len(), 4);
+    }
+
+    #[test]
+    fn into_iter_in_delivery_order() {
+        let now = Instant::now();
+        let mut queue = TimeQueue::new();
+        queue.add_item(now + Duration::from_secs(3), 3);
+        queue.add_item(now + Duration::from_secs(1), 1);
+        queue.add_item(now + Duration::from_secs(2), 2);
+        queue.add_item(now + Duration::from_secs(1), 4);
+        assert_eq!(queue.into_iter().collect::<Vec<_>>(), [1, 4, 2, 3]);
+    }
+
+    #[test]
+    fn pops_only_due_items() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(1);
+        let mut queue = TimeQueue::new();
+        queue.add_item(later, 1);
+        assert!(!queue.has_item_at(now));
+        assert!(queue.pop_entry_at(now).is_none());
+        assert!(queue.has_item_at(later));
+        let entry = queue.pop_entry_at(later).unwrap();
+        assert_eq!((entry.instant, entry.item), (later, 1));
+    }
+
+    #[test]
+    fn pop_next_entry_ignores_time() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(1);
+        let mut queue = TimeQueue::new();
+        queue.add_item(later, 1);
+        assert_eq!(queue.peek_entry().map(|entry| entry.instant), Some(later));
+        assert_eq!(queue.pop_next_entry().map(|entry| entry.item), Some(1));
+        assert!(queue.pop_next_entry().is_none());
+    }
+
+    #[test]
+    fn priority_breaks_ties() {
+        let now = Instant::now();
+        let mut queue = TimeQueue::new();
+        queue.add_item_with_priority(now, 0, "low");
+        queue.add_item_with_priority(now, 46, "high");
+        queue.add_item_with_priority(now + Duration::from_millis(1), 63, "later");
+        assert_eq!(queue.pop_item_at(now), Some("high"));
+        assert_eq!(queue.pop_item_at(now), Some("low"));
+        assert_eq!(queue.pop_item_at(now), None);
+    }
+
+    #[test]
+    fn items_need_no_bounds() {
+        struct Opaque;
+
+        let now = Instant::now();
+        let mut queue = TimeQueue::new();
+        queue.add_item(now, Opaque);
+        assert!(queue.pop_item_at(now).is_some());
+    }
+}
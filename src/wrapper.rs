@@ -0,0 +1,41 @@
+//! What the receive-side wrappers around a conditioner, such as
+//! [`JitterBuffer`](crate::jitter_buffer::JitterBuffer), share.
+
+use std::io;
+
+use crate::{random::RandomSource, Conditioner, RecvFrom, SocketLike};
+
+/// Accessors for the `conditioner` field of a wrapper generic over `S` and
+/// `R`, expanded inside its inherent impl.
+macro_rules! conditioner_accessors {
+    () => {
+        pub fn conditioner(&self) -> &Conditioner<S, R> {
+            &self.conditioner
+        }
+
+        pub fn conditioner_mut(&mut self) -> &mut Conditioner<S, R> {
+            &mut self.conditioner
+        }
+
+        pub fn into_conditioner(self) -> Conditioner<S, R> {
+            self.conditioner
+        }
+    };
+}
+
+pub(crate) use conditioner_accessors;
+
+/// Reads the next packet the conditioner delivers, as large as the
+/// conditioner itself reads off the socket.
+pub(crate) fn recv_packet<S, R>(conditioner: &Conditioner<S, R>) -> io::Result<RecvFrom>
+where
+    S: SocketLike,
+    R: RandomSource,
+{
+    let mut temp_buf = [0; 16384];
+    let (received, addr) = conditioner.recv_from(&mut temp_buf)?;
+    Ok(RecvFrom {
+        addr,
+        data: temp_buf[..received].to_vec(),
+    })
+}